@@ -0,0 +1,112 @@
+use anyhow::Result;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::future::Future;
+use tokio::sync::Semaphore;
+
+/// Most ecs describe requests accept at most this many resources
+pub const ECS_LIMIT: usize = 100;
+
+/// The most instances describe_instances returns per page, to page through as few as possible
+pub const EC2_PAGE_SIZE: i64 = 1000;
+
+/// How many requests to have in flight at once when looking up independent batches
+pub const CONCURRENT_REQUESTS: usize = 8;
+
+/// Split the ids into batches of at most size, requesting every batch concurrently. The results
+/// come back in whatever order the requests finish.
+pub async fn batched<T, F, Fut>(ids: &[String], size: usize, request: F) -> Result<Vec<T>>
+where
+    F: Fn(Vec<String>) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    concurrently(ids.chunks(size).map(<[String]>::to_vec), request).await
+}
+
+/// Run the request for every item, at most CONCURRENT_REQUESTS at a time
+pub async fn concurrently<I, T, F, Fut>(items: I, request: F) -> Result<Vec<T>>
+where
+    I: IntoIterator,
+    F: Fn(I::Item) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    stream::iter(items)
+        .map(request)
+        .buffer_unordered(CONCURRENT_REQUESTS)
+        .try_collect()
+        .await
+}
+
+/// Caps the requests in flight at CONCURRENT_REQUESTS, however deeply batches are nested. Each
+/// batch runs CONCURRENT_REQUESTS at a time on its own, so a batch within a batch would otherwise
+/// multiply them.
+pub struct Limiter(Semaphore);
+
+impl Limiter {
+    pub fn new() -> Self {
+        Self(Semaphore::new(CONCURRENT_REQUESTS))
+    }
+
+    /// Make the request once there is room for it
+    pub async fn request<T>(&self, request: impl Future<Output = T>) -> T {
+        let _permit = self.0.acquire().await.expect("the limiter is never closed");
+        request.await
+    }
+}
+
+impl Default for Limiter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{batched, concurrently, Limiter, CONCURRENT_REQUESTS};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn ids_are_requested_in_batches() {
+        let ids: Vec<String> = (0..250).map(|i| i.to_string()).collect();
+
+        let mut sizes = batched(&ids, 100, |batch| async move { Ok(batch.len()) })
+            .await
+            .unwrap();
+        sizes.sort_unstable();
+
+        assert_eq!(vec![50, 100, 100], sizes);
+        assert!(batched(&[], 100, |batch| async move { Ok(batch.len()) })
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn nested_batches_share_the_limit() {
+        let limiter = Limiter::new();
+        let in_flight = AtomicUsize::new(0);
+        let most = AtomicUsize::new(0);
+
+        let request = || {
+            limiter.request(async {
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        };
+
+        let outer: Vec<usize> = (0..CONCURRENT_REQUESTS * 2).collect();
+        concurrently(&outer, |_| async {
+            let inner: Vec<String> = (0..CONCURRENT_REQUESTS * 2)
+                .map(|i| i.to_string())
+                .collect();
+            batched(&inner, 1, |_| request()).await
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(CONCURRENT_REQUESTS, most.load(Ordering::SeqCst));
+    }
+}
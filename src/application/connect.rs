@@ -1,89 +1,266 @@
+use super::batch;
+use super::error::{aws_error, aws_error_code, AwsContext, Error};
 use anyhow::{anyhow, Context, Result};
-use rusoto_ec2::{filter, DescribeInstancesRequest, Ec2, Ec2Client};
+use chrono::{DateTime, TimeZone, Utc};
+use rusoto_ec2::{
+    filter, DescribeInstancesRequest, Ec2, Ec2Client, Filter, GetConsoleOutputRequest,
+};
+use rusoto_ec2_instance_connect::{
+    Ec2InstanceConnect, Ec2InstanceConnectClient, SendSSHPublicKeyRequest,
+};
 use rusoto_ecs::{
     DescribeContainerInstancesRequest, DescribeTasksRequest, Ecs, EcsClient, ListTasksRequest,
 };
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::net::IpAddr;
+use tokio::net::lookup_host;
+use tracing::{debug, info, instrument};
 
 // TODO(mmk) This is a smell. We probably shouldn't have to expose all of these.
+use crate::domain::cache::Cache;
 use crate::domain::connections::{
-    Connection, Connections, Container, HostConnection, SshConnection,
+    Choice, Connection, Connections, Container, HostConnection, SshConnection, Task,
 };
+use crate::domain::known_hosts;
+use crate::domain::target::{self, Target};
+
+/// Where a running ec2 instance can be found
+pub struct InstanceLocation {
+    pub instance_id: String,
+    pub availability_zone: String,
+}
+
+/// Cache key of the names of running instances, offered as targets by completion. Listing
+/// instances fills it as well.
+pub const INSTANCE_NAMES_KEY: &str = "instance names";
 
 pub struct Handler<'a> {
     ecs_client: &'a EcsClient,
     ec2_client: &'a Ec2Client,
+    instance_connect_client: &'a Ec2InstanceConnectClient,
+    cache: &'a Cache,
+    /// Whether the last connection choices came from the cache
+    cached: Cell<bool>,
 }
 
 impl<'a> Handler<'a> {
-    pub fn new(ecs_client: &'a EcsClient, ec2_client: &'a Ec2Client) -> Self {
+    pub fn new(
+        ecs_client: &'a EcsClient,
+        ec2_client: &'a Ec2Client,
+        instance_connect_client: &'a Ec2InstanceConnectClient,
+        cache: &'a Cache,
+    ) -> Self {
         Self {
             ecs_client,
             ec2_client,
+            instance_connect_client,
+            cache,
+            cached: Cell::new(false),
         }
     }
 
-    pub async fn ssh_connection_choices_for_service(
+    #[instrument(skip_all, fields(target = %target))]
+    pub async fn ssh_connection_choices(
         &self,
-        target: &str,
+        target: &Target,
     ) -> Result<Vec<Box<dyn SshConnection>>> {
-        let parts: Vec<&str> = target.split('#').take(3).collect();
-
-        match parts[..] {
-            [cluster, service] => {
-                let conns = self
-                    .build_connections_for_service(cluster, service, None)
-                    .await?;
-
-                if let Some(connection) = conns.get_connections().first() {
-                    if connection.get_containers().len() > 1 {
-                        let mut names: Vec<String> = connection
-                            .get_containers()
-                            .iter()
-                            .map(|container| container.name.clone())
-                            .collect();
-                        names.sort();
-
-                        return Err(anyhow!(format!(
-                            "Ambiguous connection options. Specify container with {}#{}#{{{}}}.",
-                            cluster,
-                            service,
-                            names.join(", ")
-                        )));
-                    }
+        let key = choices_key(target);
+        let cached = self.cache.get::<Vec<Choice>>(&key);
+        self.cached.set(cached.is_some());
+
+        let choices = match cached {
+            Some(choices) => choices,
+            None => {
+                let choices = self.fetch_ssh_connection_choices(target).await?;
+                // Nothing matching may soon change, e.g. while a service is deployed. The cache
+                // only saves time, so failing to write it is not worth failing over.
+                if !choices.is_empty() {
+                    let _ = self.cache.put(&key, &choices);
                 }
+                choices
+            }
+        };
+
+        info!(
+            choices = choices.len(),
+            cached = self.cached.get(),
+            "Found connection choices"
+        );
+
+        Ok(choices.into_iter().map(Choice::boxed).collect())
+    }
+
+    /// Whether the last connection choices came from the cache, and may be out of date
+    pub fn cached(&self) -> bool {
+        self.cached.get()
+    }
+
+    /// Drop the cached connection choices for the target, so they are looked up again
+    pub fn forget(&self, target: &Target) {
+        let _ = self.cache.remove(&choices_key(target));
+    }
 
-                Ok(conns.get_connection_choices())
+    /// Whether the instance, and task if any, of a connection choice are still running
+    #[instrument(level = "debug", skip_all, fields(instance_id = choice.instance_id(), task_id = ?choice.task_id()))]
+    pub async fn still_running(&self, target: &Target, choice: &dyn SshConnection) -> Result<bool> {
+        if let (Some(task_id), Target::Service { cluster, .. }) = (choice.task_id(), target) {
+            let request = DescribeTasksRequest {
+                cluster: Some(cluster.clone()),
+                tasks: vec![task_id.into()],
+                ..Default::default()
+            };
+
+            let result = self
+                .ecs_client
+                .describe_tasks(request)
+                .await
+                .aws_context("Unable to describe tasks")?;
+
+            let running = result
+                .tasks
+                .unwrap_or_default()
+                .iter()
+                .any(|task| task.last_status.as_deref() == Some("RUNNING"));
+            if !running {
+                debug!("Task is no longer running");
+                return Ok(false);
             }
-            [cluster, service, container] => {
-                let conns = self
-                    .build_connections_for_service(cluster, service, Some(container))
-                    .await?;
+        }
+
+        let request = DescribeInstancesRequest {
+            instance_ids: Some(vec![choice.instance_id().into()]),
+            filters: Some(vec![filter!("instance-state-name", "running")]),
+            ..Default::default()
+        };
+
+        match self.ec2_client.describe_instances(request).await {
+            Ok(result) => Ok(result
+                .reservations
+                .unwrap_or_default()
+                .into_iter()
+                .any(|reservation| !reservation.instances.unwrap_or_default().is_empty())),
+            // Terminated instances are eventually forgotten
+            Err(e) if aws_error_code(&e) == Some("InvalidInstanceID.NotFound") => Ok(false),
+            Err(e) => Err(aws_error(e, "Failed to retrieve ec2 instances")),
+        }
+    }
 
-                Ok(conns.get_connection_choices())
+    async fn fetch_ssh_connection_choices(&self, target: &Target) -> Result<Vec<Choice>> {
+        let filters = match target {
+            Target::Service {
+                cluster,
+                service,
+                container,
+            } => {
+                return self
+                    .ssh_connection_choices_for_service(cluster, service, container.as_deref())
+                    .await
+            }
+            target => instance_filters(target),
+        };
+
+        debug!(?filters, "Describing running instances");
+        self.ssh_connection_choices_for_instances(filters).await
+    }
+
+    async fn ssh_connection_choices_for_service(
+        &self,
+        cluster: &str,
+        service: &str,
+        container: Option<&str>,
+    ) -> Result<Vec<Choice>> {
+        let conns = self
+            .build_connections_for_service(cluster, service, container)
+            .await?;
+
+        if container.is_none() {
+            if let Some(connection) = conns.get_connections().first() {
+                if connection.get_containers().len() > 1 {
+                    let mut names: Vec<String> = connection
+                        .get_containers()
+                        .iter()
+                        .map(|container| container.name.clone())
+                        .collect();
+                    names.sort();
+
+                    return Err(Error::Ambiguous(format!(
+                        "Ambiguous connection options. Specify container with {}#{}#{{{}}}.",
+                        cluster,
+                        service,
+                        names.join(", ")
+                    ))
+                    .into());
+                }
             }
-            _ => Err(anyhow!("Invalid target format specified.")),
         }
+
+        Ok(conns
+            .container_choices()
+            .into_iter()
+            .map(Choice::Container)
+            .collect())
     }
 
-    pub async fn ssh_connection_choices_for_host(
+    /// Every container of every task in the service, however many containers its tasks run
+    pub async fn service_containers(
         &self,
-        host: &str,
+        cluster: &str,
+        service: &str,
     ) -> Result<Vec<Box<dyn SshConnection>>> {
-        let mut request = DescribeInstancesRequest::default();
-        request.filters = Some(vec![
-            filter!("instance-state-name", "running"),
-            filter!("tag:Name", host),
-        ]);
+        Ok(self
+            .build_connections_for_service(cluster, service, None)
+            .await?
+            .get_connection_choices())
+    }
+
+    /// The names of the containers the tasks of a service run
+    #[instrument(level = "debug", skip(self))]
+    pub async fn container_names(&self, cluster: &str, service: &str) -> Result<Vec<String>> {
+        let key = format!("containers {}#{}", cluster, service);
+        if let Some(names) = self.cache.get(&key) {
+            return Ok(names);
+        }
+
+        let mut names: Vec<String> = self
+            .build_connections_for_service(cluster, service, None)
+            .await?
+            .get_connections()
+            .iter()
+            .flat_map(|connection| connection.get_containers())
+            .map(|container| container.name)
+            .collect();
+        names.sort();
+        names.dedup();
+
+        if !names.is_empty() {
+            let _ = self.cache.put(&key, &names);
+        }
+
+        Ok(names)
+    }
+
+    async fn ssh_connection_choices_for_instances(
+        &self,
+        filters: Vec<Filter>,
+    ) -> Result<Vec<Choice>> {
+        let request = DescribeInstancesRequest {
+            filters: Some(
+                std::iter::once(filter!("instance-state-name", "running"))
+                    .chain(filters)
+                    .collect(),
+            ),
+            ..Default::default()
+        };
 
         // TODO(mmk) We need to handle pagination of results
         let result = self
             .ec2_client
             .describe_instances(request)
             .await
-            .context("Failed to retrieve ec2 instances")?;
+            .aws_context("Failed to retrieve ec2 instances")?;
 
-        let mut choices: Vec<Box<dyn SshConnection>> = Vec::new();
+        let mut choices = Vec::new();
 
         let reservations = result.reservations.unwrap_or_default();
         for reservation in reservations {
@@ -94,35 +271,229 @@ impl<'a> Handler<'a> {
                         let instance_id = instance
                             .instance_id
                             .unwrap_or_else(|| "Unknown instance id".into());
-                        choices.push(Box::new(HostConnection {
-                            name: host.into(),
+                        let name = instance
+                            .tags
+                            .unwrap_or_default()
+                            .into_iter()
+                            .find(|tag| tag.key.as_deref() == Some("Name"))
+                            .and_then(|tag| tag.value)
+                            .unwrap_or_else(|| instance_id.clone());
+                        choices.push(Choice::Host(HostConnection {
+                            name,
                             instance_id,
                             private_ip: ip,
-                        }) as Box<dyn SshConnection>);
+                            launched_at: instance
+                                .launch_time
+                                .and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+                                .map(|time| time.with_timezone(&Utc)),
+                            availability_zone: instance
+                                .placement
+                                .and_then(|placement| placement.availability_zone)
+                                .unwrap_or_default(),
+                        }));
                     }
                     _ => continue,
                 };
             }
         }
 
+        debug!(instances = choices.len(), "Described running instances");
+
         Ok(choices)
     }
 
-    async fn build_connections(&self, cluster: &str, service: &str) -> Result<Connections> {
-        let mut request = ListTasksRequest::default();
-        request.cluster = Some(cluster.into());
-        request.service_name = Some(service.into());
+    /// The Name tags of every running instance
+    #[instrument(level = "debug", skip_all)]
+    pub async fn instance_names(&self) -> Result<Vec<String>> {
+        if let Some(names) = self.cache.get(INSTANCE_NAMES_KEY) {
+            return Ok(names);
+        }
+
+        let mut names = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let request = DescribeInstancesRequest {
+                filters: Some(vec![
+                    filter!("instance-state-name", "running"),
+                    filter!("tag-key", "Name"),
+                ]),
+                max_results: Some(batch::EC2_PAGE_SIZE),
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .ec2_client
+                .describe_instances(request)
+                .await
+                .aws_context("Failed to retrieve ec2 instances")?;
+
+            for reservation in result.reservations.unwrap_or_default() {
+                for instance in reservation.instances.unwrap_or_default() {
+                    names.extend(
+                        instance
+                            .tags
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter(|tag| tag.key.as_deref() == Some("Name"))
+                            .filter_map(|tag| tag.value),
+                    );
+                }
+            }
+
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        names.sort();
+        names.dedup();
+        debug!(names = names.len(), "Described named instances");
+        let _ = self.cache.put(INSTANCE_NAMES_KEY, &names);
+
+        Ok(names)
+    }
+
+    /// The ssh host keys the instance printed to its console on boot
+    #[instrument(level = "debug", skip(self))]
+    pub async fn console_host_keys(&self, instance_id: &str) -> Result<Vec<String>> {
+        let request = GetConsoleOutputRequest {
+            instance_id: instance_id.into(),
+            ..Default::default()
+        };
 
         let result = self
-            .ecs_client
-            .list_tasks(request)
+            .ec2_client
+            .get_console_output(request)
             .await
-            .context("Unable to find tasks for specified cluster and service")?;
+            .aws_context(format!(
+                "Failed to retrieve console output of {}",
+                instance_id
+            ))?;
+
+        let output = base64::decode(result.output.unwrap_or_default())
+            .with_context(|| format!("Invalid console output from {}", instance_id))?;
+        let keys = known_hosts::console_host_keys(&String::from_utf8_lossy(&output));
+
+        if keys.is_empty() {
+            return Err(anyhow!(
+                "No ssh host keys found in the console output of {}",
+                instance_id
+            ));
+        }
+
+        debug!(keys = keys.len(), "Read host keys from console output");
+
+        Ok(keys)
+    }
+
+    /// The running instance answering to a public host name or ip address. Host names are
+    /// resolved to their addresses first, as EC2 only knows the names it assigned itself.
+    #[instrument(level = "debug", skip(self))]
+    pub async fn instance_for_address(&self, address: &str) -> Result<Option<InstanceLocation>> {
+        let mut ips: Vec<String> = match address.parse::<IpAddr>() {
+            Ok(ip) => vec![ip.to_string()],
+            Err(_) => lookup_host((address, 0))
+                .await
+                .with_context(|| format!("Unable to resolve {}", address))?
+                .map(|socket| socket.ip())
+                .filter(IpAddr::is_ipv4)
+                .map(|ip| ip.to_string())
+                .collect(),
+        };
+        ips.sort();
+        ips.dedup();
+        debug!(?ips, "Resolved bastion address");
+
+        if ips.is_empty() {
+            return Ok(None);
+        }
+
+        let request = DescribeInstancesRequest {
+            filters: Some(vec![
+                filter!("instance-state-name", "running"),
+                Filter {
+                    name: Some("ip-address".into()),
+                    values: Some(ips),
+                },
+            ]),
+            ..Default::default()
+        };
 
+        let result = self
+            .ec2_client
+            .describe_instances(request)
+            .await
+            .aws_context("Failed to retrieve ec2 instances")?;
+
+        Ok(result
+            .reservations
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|reservation| reservation.instances.unwrap_or_default())
+            .find_map(|instance| {
+                Some(InstanceLocation {
+                    instance_id: instance.instance_id?,
+                    availability_zone: instance.placement?.availability_zone?,
+                })
+            }))
+    }
+
+    /// Allow the public key to authenticate as the os user on the instance for the next 60
+    /// seconds
+    #[instrument(level = "debug", skip(self, instance, public_key), fields(instance_id = %instance.instance_id))]
+    pub async fn send_ssh_public_key(
+        &self,
+        instance: &InstanceLocation,
+        os_user: &str,
+        public_key: &str,
+    ) -> Result<()> {
+        let request = SendSSHPublicKeyRequest {
+            instance_id: instance.instance_id.clone(),
+            availability_zone: instance.availability_zone.clone(),
+            instance_os_user: os_user.into(),
+            ssh_public_key: public_key.into(),
+        };
+
+        self.instance_connect_client
+            .send_ssh_public_key(request)
+            .await
+            .aws_context(format!(
+                "Failed to send ssh public key to {}",
+                instance.instance_id
+            ))?;
+
+        Ok(())
+    }
+
+    async fn build_connections(&self, cluster: &str, service: &str) -> Result<Connections> {
         let mut connections = Connections::new();
+        let mut next_token = None;
+
+        loop {
+            let request = ListTasksRequest {
+                cluster: Some(cluster.into()),
+                service_name: Some(service.into()),
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .ecs_client
+                .list_tasks(request)
+                .await
+                .aws_context("Unable to find tasks for specified cluster and service")?;
+
+            for task_arn in result.task_arns.unwrap_or_default() {
+                connections.add_connection(arn_to_id(&task_arn).to_string(), Connection::new());
+            }
 
-        for task_arn in result.task_arns.unwrap_or_default() {
-            connections.add_connection(arn_to_id(&task_arn).to_string(), Connection::new());
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
         }
 
         Ok(connections)
@@ -134,22 +505,41 @@ impl<'a> Handler<'a> {
         container_name: Option<&str>,
         connections: &mut Connections,
     ) -> Result<()> {
-        let mut request = DescribeTasksRequest::default();
-        request.cluster = Some(cluster.into());
-        request.tasks = connections.task_ids();
-
-        let result = self
-            .ecs_client
-            .describe_tasks(request)
-            .await
-            .context("Unable to describe tasks")?;
+        let tasks = batch::batched(&connections.task_ids(), batch::ECS_LIMIT, |tasks| {
+            let request = DescribeTasksRequest {
+                cluster: Some(cluster.into()),
+                tasks,
+                ..Default::default()
+            };
+
+            async move {
+                self.ecs_client
+                    .describe_tasks(request)
+                    .await
+                    .aws_context("Unable to describe tasks")
+            }
+        })
+        .await?;
 
-        for task in result.tasks.unwrap_or_default() {
+        for task in tasks
+            .into_iter()
+            .flat_map(|result| result.tasks.unwrap_or_default())
+        {
             if task.container_instance_arn.is_none() {
                 continue;
             }
 
             let container_instance_arn = task.container_instance_arn.unwrap();
+            let details = Task {
+                task_definition: task
+                    .task_definition_arn
+                    .as_deref()
+                    .map(|arn| arn_to_id(arn).to_string()),
+                health_status: task.health_status,
+                started_at: task
+                    .started_at
+                    .and_then(|seconds| Utc.timestamp_opt(seconds as i64, 0).single()),
+            };
             for container in task.containers.unwrap_or_default() {
                 if container.runtime_id.is_none() || container.name.is_none() {
                     continue;
@@ -170,10 +560,13 @@ impl<'a> Handler<'a> {
                         task_id.clone(),
                         Container {
                             runtime_id: container.runtime_id.unwrap(),
+                            health_status: container.health_status,
                             name,
                         },
                     );
 
+                    connections.set_task(task_id.clone(), details.clone());
+
                     connections.set_container_instance_id(
                         task_id,
                         arn_to_id(&container_instance_arn).to_string(),
@@ -196,17 +589,30 @@ impl<'a> Handler<'a> {
         if connections.container_arns().is_empty() {
             return Ok(());
         }
-        let mut request = DescribeContainerInstancesRequest::default();
-        request.cluster = Some(cluster.into());
-        request.container_instances = connections.container_arns();
-
-        let result = self
-            .ecs_client
-            .describe_container_instances(request)
-            .await
-            .context("Unable to describe container instances")?;
+        let container_instances = batch::batched(
+            &connections.container_arns(),
+            batch::ECS_LIMIT,
+            |container_instances| {
+                let request = DescribeContainerInstancesRequest {
+                    cluster: Some(cluster.into()),
+                    container_instances,
+                    ..Default::default()
+                };
 
-        for container_instance in result.container_instances.unwrap_or_default() {
+                async move {
+                    self.ecs_client
+                        .describe_container_instances(request)
+                        .await
+                        .aws_context("Unable to describe container instances")
+                }
+            },
+        )
+        .await?;
+
+        for container_instance in container_instances
+            .into_iter()
+            .flat_map(|result| result.container_instances.unwrap_or_default())
+        {
             connections.set_ec2_instance_id(
                 arn_to_id(container_instance.container_instance_arn.unwrap().as_str()).to_string(),
                 container_instance.ec_2_instance_id.unwrap(),
@@ -223,19 +629,36 @@ impl<'a> Handler<'a> {
             return Ok(());
         }
 
-        let mut request = DescribeInstancesRequest::default();
-        request.instance_ids = Some(connections.instance_ids());
-
-        let result = self
-            .ec2_client
-            .describe_instances(request)
-            .await
-            .context("Unable to describe instances")?;
+        let instances = batch::batched(
+            &connections.instance_ids(),
+            batch::ECS_LIMIT,
+            |instance_ids| {
+                let request = DescribeInstancesRequest {
+                    instance_ids: Some(instance_ids),
+                    ..Default::default()
+                };
 
-        for reservation in result.reservations.unwrap_or_default() {
+                async move {
+                    self.ec2_client
+                        .describe_instances(request)
+                        .await
+                        .aws_context("Unable to describe instances")
+                }
+            },
+        )
+        .await?;
+
+        for reservation in instances
+            .into_iter()
+            .flat_map(|result| result.reservations.unwrap_or_default())
+        {
             for instance in reservation.instances.unwrap_or_default() {
                 let instance_id = instance.instance_id.unwrap();
                 let private_ip = instance.private_ip_address.unwrap();
+                let availability_zone = instance
+                    .placement
+                    .and_then(|placement| placement.availability_zone)
+                    .unwrap_or_default();
 
                 let tag_map = instance
                     .tags
@@ -246,13 +669,14 @@ impl<'a> Handler<'a> {
 
                 let name = tag_map.get("Name").unwrap().to_owned();
 
-                connections.set_name_and_ip(instance_id, name, private_ip);
+                connections.set_name_and_ip(instance_id, name, private_ip, availability_zone);
             }
         }
 
         Ok(())
     }
 
+    #[instrument(level = "debug", skip(self))]
     async fn build_connections_for_service(
         &self,
         cluster: &str,
@@ -260,23 +684,59 @@ impl<'a> Handler<'a> {
         container: Option<&str>,
     ) -> Result<Connections> {
         let mut connections = self.build_connections(cluster, service).await?;
+        debug!(tasks = connections.task_ids().len(), "Listed tasks");
+
         self.add_containers_to_connections(cluster, container, &mut connections)
             .await?;
+        debug!(
+            container_instances = connections.container_arns().len(),
+            "Described tasks"
+        );
+
         self.add_ec2_instance_ids_to_connections(cluster, &mut connections)
             .await?;
+        debug!(
+            instances = connections.instance_ids().len(),
+            "Described container instances"
+        );
+
         self.add_name_and_ip(&mut connections).await?;
+        debug!("Described instances");
 
         Ok(connections)
     }
 }
 
-fn arn_to_id(arn: &str) -> &str {
-    arn.split('/').last().unwrap_or_default()
+fn choices_key(target: &Target) -> String {
+    format!("choices {}", target)
+}
+
+/// The id at the end of an ecs resource arn
+pub fn arn_to_id(arn: &str) -> &str {
+    arn.split('/').next_back().unwrap_or_default()
+}
+
+/// The filters selecting the instances an instance target names. Service targets are looked up
+/// through ecs instead, and match no instances.
+fn instance_filters(target: &Target) -> Vec<Filter> {
+    match target {
+        Target::InstanceId(id) => vec![filter!("instance-id", id)],
+        Target::PrivateIp(ip) => vec![filter!("private-ip-address", ip)],
+        Target::Tag { key, value } => vec![filter!(format!("tag:{}", key), value)],
+        Target::Environment { environment, name } => vec![
+            filter!(format!("tag:{}", target::ENVIRONMENT_TAG), environment),
+            filter!("tag:Name", name),
+        ],
+        Target::Name(name) => vec![filter!("tag:Name", name)],
+        Target::Service { .. } => vec![],
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::arn_to_id;
+    use super::{arn_to_id, instance_filters};
+    use crate::domain::target::Target;
+    use rusoto_ec2::filter;
 
     #[test]
     fn arn_to_id_works_as_expected() {
@@ -287,4 +747,15 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn targets_taken_from_list_output_filter_on_the_tags_it_shows() {
+        // list shows an instance tagged Env=staging and Name=api as api under staging
+        let target: Target = "staging/api".parse().unwrap();
+
+        assert_eq!(
+            vec![filter!("tag:Env", "staging"), filter!("tag:Name", "api")],
+            instance_filters(&target)
+        );
+    }
 }
@@ -0,0 +1,150 @@
+use super::error::AwsContext;
+use anyhow::Result;
+use rusoto_ec2::{
+    filter, DescribeInstancesRequest, DescribeSecurityGroupsRequest, Ec2, Ec2Client, IpPermission,
+};
+use std::collections::HashMap;
+
+/// A running instance reachable from the internet, which may be a bastion server
+pub struct BastionCandidate {
+    pub name: String,
+    pub instance_id: String,
+    pub dns_name: String,
+    pub security_group_ids: Vec<String>,
+}
+
+/// A security group allowing ssh ingress
+pub struct SecurityGroupCandidate {
+    pub group_id: String,
+    pub name: String,
+}
+
+pub struct Handler<'a> {
+    client: &'a Ec2Client,
+}
+
+impl<'a> Handler<'a> {
+    pub fn new(client: &'a Ec2Client) -> Self {
+        Self { client }
+    }
+
+    pub async fn bastion_candidates(&self) -> Result<Vec<BastionCandidate>> {
+        let mut candidates = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let request = DescribeInstancesRequest {
+                filters: Some(vec![filter!("instance-state-name", "running")]),
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .client
+                .describe_instances(request)
+                .await
+                .aws_context("Failed to retrieve ec2 instances")?;
+
+            for reservation in result.reservations.unwrap_or_default() {
+                for instance in reservation.instances.unwrap_or_default() {
+                    let dns_name = match (instance.public_dns_name, instance.public_ip_address) {
+                        (Some(dns_name), _) if !dns_name.is_empty() => dns_name,
+                        (_, Some(ip)) => ip,
+                        _ => continue,
+                    };
+
+                    let tag_map = instance
+                        .tags
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|tag| (tag.key.clone().unwrap(), tag.value.clone().unwrap()))
+                        .collect::<HashMap<String, String>>();
+
+                    let instance_id = instance
+                        .instance_id
+                        .unwrap_or_else(|| "Unknown instance id".into());
+
+                    candidates.push(BastionCandidate {
+                        name: tag_map
+                            .get("Name")
+                            .cloned()
+                            .unwrap_or_else(|| instance_id.clone()),
+                        instance_id,
+                        dns_name,
+                        security_group_ids: instance
+                            .security_groups
+                            .unwrap_or_default()
+                            .into_iter()
+                            .filter_map(|group| group.group_id)
+                            .collect(),
+                    });
+                }
+            }
+
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    /// Security groups with an ingress rule covering port 22. If group ids are given, only those
+    /// groups are considered.
+    pub async fn ssh_security_groups(
+        &self,
+        group_ids: Vec<String>,
+    ) -> Result<Vec<SecurityGroupCandidate>> {
+        let mut candidates = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let request = DescribeSecurityGroupsRequest {
+                group_ids: if group_ids.is_empty() {
+                    None
+                } else {
+                    Some(group_ids.clone())
+                },
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .client
+                .describe_security_groups(request)
+                .await
+                .aws_context("Failed to retrieve security groups")?;
+
+            for group in result.security_groups.unwrap_or_default() {
+                let allows_ssh = group
+                    .ip_permissions
+                    .unwrap_or_default()
+                    .iter()
+                    .any(allows_ssh);
+
+                if let (true, Some(group_id)) = (allows_ssh, group.group_id) {
+                    candidates.push(SecurityGroupCandidate {
+                        group_id,
+                        name: group.group_name.unwrap_or_default(),
+                    });
+                }
+            }
+
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(candidates)
+    }
+}
+
+fn allows_ssh(permission: &IpPermission) -> bool {
+    match (permission.from_port, permission.to_port) {
+        (Some(from), Some(to)) => from <= 22 && to >= 22,
+        // Rules for all protocols have no port range
+        _ => permission.ip_protocol.as_deref() == Some("-1"),
+    }
+}
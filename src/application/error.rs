@@ -0,0 +1,138 @@
+use rusoto_core::RusotoError;
+use std::error::Error as StdError;
+use std::fmt;
+use thiserror::Error;
+
+/// Error codes AWS services use when a request could not be authenticated or authorized
+const AUTH_ERROR_CODES: &[&str] = &[
+    "AccessDenied",
+    "AccessDeniedException",
+    "AuthFailure",
+    "ExpiredToken",
+    "ExpiredTokenException",
+    "InvalidClientTokenId",
+    "InvalidSignatureException",
+    "MissingAuthenticationToken",
+    "RequestExpired",
+    "SignatureDoesNotMatch",
+    "UnauthorizedOperation",
+    "UnrecognizedClientException",
+];
+
+/// Failures scripts may want to tell apart, each with its own exit code
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No target matches {0}")]
+    NoMatch(String),
+
+    #[error("{0}")]
+    Ambiguous(String),
+
+    #[error("{0}")]
+    Auth(String),
+
+    #[error("{0}")]
+    Network(String),
+
+    #[error("ssh session exited with status {0}")]
+    RemoteCommand(i32),
+}
+
+impl Error {
+    /// The exit code documented for this failure. Any other error exits with 1.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::NoMatch(_) => 2,
+            Error::Ambiguous(_) => 3,
+            Error::Auth(_) => 4,
+            Error::Network(_) => 5,
+            Error::RemoteCommand(_) => 6,
+        }
+    }
+}
+
+pub trait AwsContext<T> {
+    /// Wrap a failed AWS call with context, as anyhow's context does
+    fn aws_context<C>(self, context: C) -> anyhow::Result<T>
+    where
+        C: fmt::Display + Send + Sync + 'static;
+}
+
+impl<T, E> AwsContext<T> for Result<T, RusotoError<E>>
+where
+    E: StdError + Send + Sync + 'static,
+{
+    fn aws_context<C>(self, context: C) -> anyhow::Result<T>
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        self.map_err(|error| aws_error(error, context))
+    }
+}
+
+/// Add context to a failed AWS call, turning authentication and network failures into an Error
+pub fn aws_error<E, C>(error: RusotoError<E>, context: C) -> anyhow::Error
+where
+    E: StdError + Send + Sync + 'static,
+    C: fmt::Display + Send + Sync + 'static,
+{
+    let auth_failure = match &error {
+        RusotoError::Credentials(_) => true,
+        RusotoError::Unknown(response) => {
+            response.status.as_u16() == 401
+                || response.status.as_u16() == 403
+                || aws_error_code(&error)
+                    .map(|code| AUTH_ERROR_CODES.contains(&code))
+                    .unwrap_or(false)
+        }
+        _ => false,
+    };
+
+    match error {
+        _ if auth_failure => Error::Auth(format!("{}: {}", context, error)).into(),
+        RusotoError::HttpDispatch(_) => Error::Network(format!("{}: {}", context, error)).into(),
+        error => anyhow::Error::new(error).context(context),
+    }
+}
+
+/// The error code of an unmodeled AWS error
+pub fn aws_error_code<E>(err: &RusotoError<E>) -> Option<&str> {
+    match err {
+        RusotoError::Unknown(response) => error_code(response.body_as_str()),
+        _ => None,
+    }
+}
+
+/// EC2 reports every failure as an unmodeled error with an xml body, so the code has to be pulled
+/// out by hand. Json services report it as `__type`, optionally prefixed with a namespace.
+fn error_code(body: &str) -> Option<&str> {
+    if let Some(start) = body.find("<Code>") {
+        let start = start + "<Code>".len();
+        let end = body[start..].find("</Code>")? + start;
+
+        return Some(&body[start..end]);
+    }
+
+    let start = body.find("\"__type\"")? + "\"__type\"".len();
+    let start = body[start..].find('"')? + start + 1;
+    let end = body[start..].find('"')? + start;
+    let code = &body[start..end];
+
+    Some(code.rsplit('#').next().unwrap_or(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::error_code;
+
+    #[test]
+    fn error_code_is_extracted_from_response_body() {
+        let body = "<Response><Errors><Error><Code>InvalidPermission.Duplicate</Code><Message>the specified rule already exists</Message></Error></Errors></Response>";
+
+        assert_eq!(Some("InvalidPermission.Duplicate"), error_code(body));
+
+        let body = r#"{"__type":"com.amazonaws.ecs#AccessDeniedException","message":"denied"}"#;
+        assert_eq!(Some("AccessDeniedException"), error_code(body));
+        assert_eq!(None, error_code("Internal Server Error"));
+    }
+}
@@ -0,0 +1,244 @@
+use super::batch;
+use super::connect::arn_to_id;
+use super::error::AwsContext;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use rusoto_ec2::{filter, DescribeInstancesRequest, Ec2, Ec2Client};
+use rusoto_ecs::{
+    DescribeServicesRequest, Ecs, EcsClient, ListClustersRequest, ListServicesRequest,
+};
+
+use crate::domain::cache::Cache;
+use crate::domain::inventory::{Instance, Service};
+use crate::domain::target;
+
+/// describe_services accepts at most this many services per request
+const DESCRIBE_SERVICES_LIMIT: usize = 10;
+
+/// Cache key of the cluster#service names of every service, offered as targets by completion.
+/// Describing every service fills it as well.
+const SERVICE_NAMES_KEY: &str = "service names";
+
+pub struct Handler<'a> {
+    ec2_client: &'a Ec2Client,
+    ecs_client: &'a EcsClient,
+    cache: &'a Cache,
+    /// Services are listed and described cluster by cluster, in batches within batches
+    limiter: batch::Limiter,
+}
+
+impl<'a> Handler<'a> {
+    pub fn new(ec2_client: &'a Ec2Client, ecs_client: &'a EcsClient, cache: &'a Cache) -> Self {
+        Self {
+            ec2_client,
+            ecs_client,
+            cache,
+            limiter: batch::Limiter::new(),
+        }
+    }
+
+    /// Every running instance, sorted by environment and name
+    pub async fn instances(&self) -> Result<Vec<Instance>> {
+        let mut instances = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let request = DescribeInstancesRequest {
+                filters: Some(vec![filter!("instance-state-name", "running")]),
+                max_results: Some(batch::EC2_PAGE_SIZE),
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .ec2_client
+                .describe_instances(request)
+                .await
+                .aws_context("Failed to retrieve ec2 instances")?;
+
+            for reservation in result.reservations.unwrap_or_default() {
+                for instance in reservation.instances.unwrap_or_default() {
+                    let instance_id = instance
+                        .instance_id
+                        .unwrap_or_else(|| "Unknown instance id".into());
+                    let tags = instance.tags.unwrap_or_default();
+                    let tag = |key: &str| {
+                        tags.iter()
+                            .find(|tag| tag.key.as_deref() == Some(key))
+                            .and_then(|tag| tag.value.clone())
+                    };
+
+                    instances.push(Instance {
+                        environment: tag(target::ENVIRONMENT_TAG),
+                        name: tag("Name").unwrap_or_else(|| instance_id.clone()),
+                        private_ip: instance.private_ip_address,
+                        availability_zone: instance
+                            .placement
+                            .and_then(|placement| placement.availability_zone)
+                            .unwrap_or_default(),
+                        launched_at: instance
+                            .launch_time
+                            .and_then(|time| DateTime::parse_from_rfc3339(&time).ok())
+                            .map(|time| time.with_timezone(&Utc)),
+                        instance_id,
+                    });
+                }
+            }
+
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        instances
+            .sort_by(|lhs, rhs| (&lhs.environment, &lhs.name).cmp(&(&rhs.environment, &rhs.name)));
+
+        Ok(instances)
+    }
+
+    /// Every service of every ecs cluster, sorted by cluster and name
+    pub async fn services(&self) -> Result<Vec<Service>> {
+        let clusters = self.clusters().await?;
+        let mut services: Vec<Service> =
+            batch::concurrently(&clusters, |cluster| self.cluster_services(cluster))
+                .await?
+                .into_iter()
+                .flatten()
+                .collect();
+
+        services.sort_by(|lhs, rhs| (&lhs.cluster, &lhs.name).cmp(&(&rhs.cluster, &rhs.name)));
+
+        // Every service was just found, so the names need no lookup of their own
+        let names: Vec<String> = services
+            .iter()
+            .map(|service| format!("{}#{}", service.cluster, service.name))
+            .collect();
+        let _ = self.cache.put(SERVICE_NAMES_KEY, &names);
+
+        Ok(services)
+    }
+
+    /// Every service of every ecs cluster as cluster#service, without describing them
+    pub async fn service_names(&self) -> Result<Vec<String>> {
+        if let Some(names) = self.cache.get(SERVICE_NAMES_KEY) {
+            return Ok(names);
+        }
+
+        let clusters = self.clusters().await?;
+        let mut names: Vec<String> = batch::concurrently(&clusters, |cluster| async move {
+            Ok(self
+                .service_arns(cluster)
+                .await?
+                .iter()
+                .map(|arn| format!("{}#{}", cluster, arn_to_id(arn)))
+                .collect::<Vec<String>>())
+        })
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+
+        names.sort();
+        let _ = self.cache.put(SERVICE_NAMES_KEY, &names);
+
+        Ok(names)
+    }
+
+    async fn cluster_services(&self, cluster: &str) -> Result<Vec<Service>> {
+        let arns = self.service_arns(cluster).await?;
+
+        let results = batch::batched(&arns, DESCRIBE_SERVICES_LIMIT, |services| {
+            let request = DescribeServicesRequest {
+                cluster: Some(cluster.into()),
+                services,
+                ..Default::default()
+            };
+
+            async move {
+                self.limiter
+                    .request(self.ecs_client.describe_services(request))
+                    .await
+                    .aws_context(format!("Unable to describe services of {}", cluster))
+            }
+        })
+        .await?;
+
+        Ok(results
+            .into_iter()
+            .flat_map(|result| result.services.unwrap_or_default())
+            .map(|service| Service {
+                cluster: cluster.into(),
+                name: service.service_name.unwrap_or_default(),
+                status: service.status.unwrap_or_default(),
+                running_count: service.running_count.unwrap_or_default(),
+                desired_count: service.desired_count.unwrap_or_default(),
+                task_definition: service
+                    .task_definition
+                    .as_deref()
+                    .map(|arn| arn_to_id(arn).to_string()),
+            })
+            .collect())
+    }
+
+    async fn clusters(&self) -> Result<Vec<String>> {
+        let mut clusters = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let request = ListClustersRequest {
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .ecs_client
+                .list_clusters(request)
+                .await
+                .aws_context("Unable to list ecs clusters")?;
+
+            clusters.extend(
+                result
+                    .cluster_arns
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|arn| arn_to_id(arn).to_string()),
+            );
+
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(clusters)
+    }
+
+    async fn service_arns(&self, cluster: &str) -> Result<Vec<String>> {
+        let mut arns = Vec::new();
+        let mut next_token = None;
+
+        loop {
+            let request = ListServicesRequest {
+                cluster: Some(cluster.into()),
+                next_token,
+                ..Default::default()
+            };
+
+            let result = self
+                .limiter
+                .request(self.ecs_client.list_services(request))
+                .await
+                .aws_context(format!("Unable to list services of {}", cluster))?;
+
+            arns.extend(result.service_arns.unwrap_or_default());
+
+            next_token = result.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(arns)
+    }
+}
@@ -1,34 +1,57 @@
-use anyhow::{Context, Result};
+use super::batch;
+use super::connect;
+use super::error::AwsContext;
+use crate::domain::cache::Cache;
+use crate::domain::target;
+use anyhow::Result;
 use rusoto_ec2::{filter, DescribeInstancesRequest, Ec2, Ec2Client};
 use std::collections::HashMap;
+use tracing::{debug, instrument};
+
+/// Cache key of the running instances, by environment
+const KEY: &str = "instances by environment";
 
 pub struct Handler<'a> {
     client: &'a Ec2Client,
+    cache: &'a Cache,
 }
 
 impl<'a> Handler<'a> {
-    pub fn new(client: &'a Ec2Client) -> Self {
-        Self { client }
+    pub fn new(client: &'a Ec2Client, cache: &'a Cache) -> Self {
+        Self { client, cache }
     }
 
+    #[instrument(skip_all)]
     pub async fn list(self) -> Result<HashMap<String, Vec<(String, String)>>> {
+        if let Some(running_instances) = self.cache.get(KEY) {
+            debug!("Using cached instances");
+            return Ok(running_instances);
+        }
+
         let mut running_instances: HashMap<String, Vec<(String, String)>> = HashMap::new();
 
         let mut next_token = None;
 
         loop {
-            let mut request = DescribeInstancesRequest::default();
-            request.filters = Some(vec![filter!("instance-state-name", "running")]);
-            request.next_token = next_token;
+            let request = DescribeInstancesRequest {
+                filters: Some(vec![filter!("instance-state-name", "running")]),
+                max_results: Some(batch::EC2_PAGE_SIZE),
+                next_token,
+                ..Default::default()
+            };
 
-            // TODO(mmk) We need to handle the next_token functionality so we can retrieve all matches
             let result = self
                 .client
                 .describe_instances(request)
                 .await
-                .context("Failed to retrieve ec2 instances")?;
+                .aws_context("Failed to retrieve ec2 instances")?;
 
             let reservations = result.reservations.unwrap_or_default();
+            debug!(
+                reservations = reservations.len(),
+                more = result.next_token.is_some(),
+                "Described running instances"
+            );
 
             for reservation in reservations {
                 let instances = reservation.instances.unwrap_or_default();
@@ -44,12 +67,12 @@ impl<'a> Handler<'a> {
                         .map(|tag| (tag.key.clone().unwrap(), tag.value.clone().unwrap()))
                         .collect::<HashMap<String, String>>();
 
-                    let env = tag_map.get("Env").unwrap().to_owned();
+                    let env = tag_map.get(target::ENVIRONMENT_TAG).unwrap().to_owned();
                     let name = tag_map.get("Name").unwrap().to_owned();
 
                     running_instances
                         .entry(env)
-                        .or_insert_with(Vec::new)
+                        .or_default()
                         .push((name, instance_id));
                 }
             }
@@ -60,6 +83,18 @@ impl<'a> Handler<'a> {
             }
         }
 
+        let _ = self.cache.put(KEY, &running_instances);
+
+        // The instances listed are every running one, so their names are complete
+        let mut names: Vec<&String> = running_instances
+            .values()
+            .flatten()
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+        names.dedup();
+        let _ = self.cache.put(connect::INSTANCE_NAMES_KEY, &names);
+
         Ok(running_instances)
     }
 }
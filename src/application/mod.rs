@@ -1,3 +1,39 @@
+use crate::credentials;
+use crate::settings::Profile;
+use anyhow::Result;
+use retry::Retrying;
+use rusoto_core::{region, HttpClient};
+use rusoto_ec2::Ec2Client;
+use rusoto_ec2_instance_connect::Ec2InstanceConnectClient;
+use rusoto_ecs::EcsClient;
+
+pub mod batch;
 pub mod connect;
+pub mod discovery;
+pub mod error;
+pub mod inventory;
 pub mod list_instances;
+pub mod retry;
 pub mod security_groups;
+
+/// The AWS clients handlers talk to, authenticated as a heimdallr profile
+pub struct Clients {
+    pub ec2: Ec2Client,
+    pub ecs: EcsClient,
+    pub instance_connect: Ec2InstanceConnectClient,
+}
+
+impl Clients {
+    pub fn new(name: &str, profile: &Profile) -> Result<Self> {
+        let region = profile.aws_region.parse::<region::Region>()?;
+        let provider = credentials::Provider::new(name, profile, region.clone());
+        let policy = retry::Policy::new(profile);
+        let dispatcher = || -> Result<_> { Ok(Retrying::new(HttpClient::new()?, policy)) };
+
+        Ok(Self {
+            ec2: Ec2Client::new_with(dispatcher()?, provider.clone(), region.clone()),
+            ecs: EcsClient::new_with(dispatcher()?, provider.clone(), region.clone()),
+            instance_connect: Ec2InstanceConnectClient::new_with(dispatcher()?, provider, region),
+        })
+    }
+}
@@ -0,0 +1,303 @@
+use crate::settings::Profile;
+use rand::Rng;
+use rusoto_core::request::{DispatchSignedRequestFuture, HttpResponse};
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, DispatchSignedRequest, HttpDispatchError};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time;
+use tracing::debug;
+
+/// The delay before the first retry, doubling with every attempt after it
+const BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// The longest delay between attempts
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Error codes AWS answers with when requests are being throttled
+const THROTTLING_CODES: &[&str] = &[
+    "Throttling",
+    "ThrottlingException",
+    "RequestLimitExceeded",
+    "RequestThrottled",
+    "TooManyRequestsException",
+];
+
+/// How long AWS requests may take and how often they are attempted
+#[derive(Clone, Copy, Debug)]
+pub struct Policy {
+    pub timeout: Duration,
+    pub max_attempts: u32,
+}
+
+impl Policy {
+    pub fn new(profile: &Profile) -> Self {
+        Self {
+            timeout: Duration::from_secs(profile.request_timeout),
+            max_attempts: profile.max_attempts,
+        }
+    }
+}
+
+/// Dispatches the requests of every AWS client, abandoning attempts which outlast the timeout and
+/// retrying throttled requests and transient failures with exponential backoff and jitter.
+/// Requests which change something are only retried once AWS has said it turned them away.
+pub struct Retrying<D> {
+    inner: Arc<D>,
+    policy: Policy,
+}
+
+impl<D> Retrying<D> {
+    pub fn new(inner: D, policy: Policy) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            policy,
+        }
+    }
+}
+
+impl<D: DispatchSignedRequest + Send + Sync + 'static> DispatchSignedRequest for Retrying<D> {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let inner = self.inner.clone();
+        let policy = self.policy;
+
+        Box::pin(async move {
+            let mut request = request;
+            let mut attempt = 1;
+            let mutating = mutating(&request);
+
+            loop {
+                // Streamed payloads cannot be sent twice, so those requests are only attempted once
+                let next = copy(&request);
+
+                let (result, retryable) =
+                    match attempt_once(&*inner, request, timeout, policy).await {
+                        Ok(response) if response.status.as_u16() < 400 => return Ok(response),
+                        Ok(mut response) => {
+                            let buffered = response.buffer().await?;
+                            let retryable =
+                                retryable(buffered.status.as_u16(), &buffered.body, mutating);
+                            response.body = ByteStream::from(buffered.body.to_vec());
+                            (Ok(response), retryable)
+                        }
+                        // The request may have reached AWS before the connection failed or the
+                        // attempt was abandoned
+                        Err(e) => (Err(e), !mutating),
+                    };
+
+                match next {
+                    Some(next) if retryable && attempt < policy.max_attempts => {
+                        let delay = backoff(attempt);
+                        debug!(attempt, ?delay, "Retrying {} request", next.service);
+                        time::sleep(delay).await;
+                        request = next;
+                        attempt += 1;
+                    }
+                    _ => return result,
+                }
+            }
+        })
+    }
+}
+
+async fn attempt_once<D: DispatchSignedRequest>(
+    inner: &D,
+    request: SignedRequest,
+    timeout: Option<Duration>,
+    policy: Policy,
+) -> Result<HttpResponse, HttpDispatchError> {
+    match time::timeout(policy.timeout, inner.dispatch(request, timeout)).await {
+        Ok(result) => result,
+        Err(_) => Err(HttpDispatchError::new(format!(
+            "No response from AWS within {} seconds",
+            policy.timeout.as_secs()
+        ))),
+    }
+}
+
+/// Whether a failed response is worth trying again: AWS throttled the request, or failed in a way
+/// which is likely to pass. AWS may have carried out a mutating request before failing, so those
+/// are only tried again when throttled.
+fn retryable(status: u16, body: &[u8], mutating: bool) -> bool {
+    let text = String::from_utf8_lossy(body);
+    if THROTTLING_CODES.iter().any(|code| text.contains(code)) {
+        return true;
+    }
+
+    !mutating && matches!(status, 429 | 500 | 502 | 503 | 504)
+}
+
+/// Whether the request is an EC2 action which changes something, such as
+/// AuthorizeSecurityGroupIngress. Only lookups are safe to send twice.
+fn mutating(request: &SignedRequest) -> bool {
+    if request.service != "ec2" {
+        return false;
+    }
+
+    let action = match &request.payload {
+        Some(SignedRequestPayload::Buffer(bytes)) => String::from_utf8_lossy(bytes)
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("Action="))
+            .map(String::from),
+        _ => request.params.get("Action").cloned().flatten(),
+    };
+
+    !matches!(action, Some(action) if action.starts_with("Describe") || action.starts_with("Get"))
+}
+
+/// A random delay of up to BASE_DELAY doubled for every attempt so far, capped at MAX_DELAY
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_DELAY);
+
+    rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+}
+
+/// A copy of the signed request to send again, unless its payload is streamed
+fn copy(request: &SignedRequest) -> Option<SignedRequest> {
+    let payload = match &request.payload {
+        Some(SignedRequestPayload::Buffer(bytes)) => {
+            Some(SignedRequestPayload::Buffer(bytes.clone()))
+        }
+        Some(SignedRequestPayload::Stream(_)) => return None,
+        None => None,
+    };
+
+    Some(SignedRequest {
+        method: request.method.clone(),
+        service: request.service.clone(),
+        region: request.region.clone(),
+        path: request.path.clone(),
+        headers: request.headers.clone(),
+        params: request.params.clone(),
+        scheme: request.scheme.clone(),
+        hostname: request.hostname.clone(),
+        payload,
+        canonical_query_string: request.canonical_query_string.clone(),
+        canonical_uri: request.canonical_uri.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{backoff, retryable, Policy, Retrying, MAX_DELAY};
+    use http::StatusCode;
+    use rusoto_core::request::{DispatchSignedRequestFuture, HttpResponse};
+    use rusoto_core::signature::SignedRequest;
+    use rusoto_core::{ByteStream, DispatchSignedRequest, HttpDispatchError, Region};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    const THROTTLED: &str =
+        "<Response><Errors><Error><Code>RequestLimitExceeded</Code></Error></Errors></Response>";
+
+    /// Answers every attempt with the next of its outcomes, a status or a dropped connection
+    struct Fake {
+        outcomes: Mutex<VecDeque<Option<(u16, &'static str)>>>,
+        attempts: Arc<Mutex<u32>>,
+    }
+
+    impl DispatchSignedRequest for Fake {
+        fn dispatch(
+            &self,
+            _request: SignedRequest,
+            _timeout: Option<Duration>,
+        ) -> DispatchSignedRequestFuture {
+            *self.attempts.lock().unwrap() += 1;
+            let outcome = self.outcomes.lock().unwrap().pop_front().flatten();
+
+            Box::pin(async move {
+                match outcome {
+                    Some((status, body)) => Ok(HttpResponse {
+                        status: StatusCode::from_u16(status).unwrap(),
+                        body: ByteStream::from(body.as_bytes().to_vec()),
+                        headers: Default::default(),
+                    }),
+                    None => Err(HttpDispatchError::new("connection reset".into())),
+                }
+            })
+        }
+    }
+
+    /// The status the action ends with and how many attempts it took
+    async fn dispatch(action: &str, outcomes: Vec<Option<(u16, &'static str)>>) -> (u16, u32) {
+        let attempts = Arc::new(Mutex::new(0));
+        let retrying = Retrying::new(
+            Fake {
+                outcomes: Mutex::new(outcomes.into()),
+                attempts: attempts.clone(),
+            },
+            Policy {
+                timeout: Duration::from_secs(5),
+                max_attempts: 3,
+            },
+        );
+
+        let mut request = SignedRequest::new("POST", "ec2", &Region::UsEast1, "/");
+        request.set_payload(Some(format!("Action={}&Version=2016-11-15", action)));
+
+        let status = match retrying.dispatch(request, None).await {
+            Ok(response) => response.status.as_u16(),
+            Err(_) => 0,
+        };
+        let attempts = *attempts.lock().unwrap();
+
+        (status, attempts)
+    }
+
+    #[tokio::test]
+    async fn lookups_are_retried_but_changes_only_when_throttled() {
+        let ok = Some((200, ""));
+        let throttled = Some((503, THROTTLED));
+        let unavailable = Some((503, ""));
+
+        assert_eq!(
+            (200, 3),
+            dispatch("DescribeInstances", vec![None, unavailable, ok]).await
+        );
+        assert_eq!(
+            (200, 2),
+            dispatch("AuthorizeSecurityGroupIngress", vec![throttled, ok]).await
+        );
+        assert_eq!(
+            (0, 1),
+            dispatch("AuthorizeSecurityGroupIngress", vec![None, ok]).await
+        );
+        assert_eq!(
+            (503, 1),
+            dispatch("RevokeSecurityGroupIngress", vec![unavailable, ok]).await
+        );
+    }
+
+    #[test]
+    fn throttling_and_transient_failures_are_retried() {
+        assert!(retryable(503, THROTTLED.as_bytes(), false));
+        assert!(retryable(503, THROTTLED.as_bytes(), true));
+        assert!(retryable(
+            400,
+            br#"{"__type":"ThrottlingException","message":"Rate exceeded"}"#,
+            false
+        ));
+        assert!(retryable(500, b"", false));
+        assert!(!retryable(500, b"", true));
+        assert!(!retryable(
+            400,
+            br#"{"__type":"ClusterNotFoundException","message":"Cluster not found."}"#,
+            false
+        ));
+        assert!(!retryable(
+            403,
+            b"<Code>UnauthorizedOperation</Code>",
+            false
+        ));
+
+        assert!(backoff(1) <= Duration::from_millis(200));
+        assert!(backoff(30) <= MAX_DELAY);
+    }
+}
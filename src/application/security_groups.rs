@@ -1,52 +1,143 @@
+use super::error::{aws_error, aws_error_code};
 use anyhow::{Context, Result};
+use rusoto_core::RusotoError;
 use rusoto_ec2::{
-    AuthorizeSecurityGroupIngressRequest, Ec2, Ec2Client, IpPermission, IpRange,
-    RevokeSecurityGroupIngressRequest,
+    AuthorizeSecurityGroupIngressRequest, DescribeSecurityGroupsRequest, Ec2, Ec2Client,
+    IpPermission, IpRange, RevokeSecurityGroupIngressRequest,
 };
+use std::error::Error as StdError;
+use thiserror::Error;
+use tracing::{debug, instrument};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Security group {0} does not exist")]
+    MissingGroup(String),
+
+    #[error("You are not permitted to modify security group {0}")]
+    PermissionDenied(String),
+
+    #[error("Security group {0} has reached the maximum number of rules")]
+    RuleLimitReached(String),
+
+    #[error("Your ip is still allowed into security group {0} by a broader rule, which was not added by heimdallr and is left in place")]
+    BroaderRule(String),
+}
+
+/// Whether a grant or revoke modified the security group, or found it already in the requested
+/// state.
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Applied,
+    Unchanged,
+    /// The rule that would have been added or removed had this not been a dry run
+    Planned(IpPermission),
+}
 
 pub struct Handler<'a> {
     client: &'a Ec2Client,
+    dry_run: bool,
 }
 
 impl<'a> Handler<'a> {
-    pub fn new(client: &'a Ec2Client) -> Self {
-        Self { client }
+    pub fn new(client: &'a Ec2Client, dry_run: bool) -> Self {
+        Self { client, dry_run }
     }
 
+    #[instrument(skip(self), fields(dry_run = self.dry_run))]
     pub async fn grant_access(
         &self,
         security_group_id: String,
         description: Option<String>,
-    ) -> Result<()> {
-        let mut request = AuthorizeSecurityGroupIngressRequest::default();
-        request.group_id = Some(security_group_id);
-        request.ip_permissions = Some(vec![self.get_ip_permission(description).await?]);
+    ) -> Result<Change> {
+        let permission = self.get_ip_permission(description).await?;
+        let existing = self.permissions(security_group_id.clone()).await?;
 
-        self.client
-            .authorize_security_group_ingress(request)
-            .await
-            .context("Failed to add public ip to allowlist")?;
+        if allowed(existing, &permission) {
+            debug!("Public ip is already allowed");
+            return Ok(Change::Unchanged);
+        }
+
+        let request = AuthorizeSecurityGroupIngressRequest {
+            group_id: Some(security_group_id.clone()),
+            ip_permissions: Some(vec![permission.clone()]),
+            dry_run: Some(self.dry_run),
+            ..Default::default()
+        };
 
-        Ok(())
+        match self.client.authorize_security_group_ingress(request).await {
+            Ok(_) => Ok(Change::Applied),
+            // A duplicate means someone else granted the same ip between our check and our
+            // request.
+            Err(e) => rejected(
+                aws_error_code(&e),
+                "InvalidPermission.Duplicate",
+                permission,
+            )
+            .ok_or_else(|| classify(security_group_id, e))
+            .context("Failed to add public ip to allowlist"),
+        }
     }
 
-    pub async fn revoke_access(&self, security_group_id: String) -> Result<()> {
-        let mut request = RevokeSecurityGroupIngressRequest::default();
-        request.group_id = Some(security_group_id);
-        request.ip_permissions = Some(vec![self.get_ip_permission(None).await?]);
+    #[instrument(skip(self), fields(dry_run = self.dry_run))]
+    pub async fn revoke_access(&self, security_group_id: String) -> Result<Change> {
+        let permission = self.get_ip_permission(None).await?;
+        let existing = self.permissions(security_group_id.clone()).await?;
+
+        // Only the exact rule a grant adds is removed. A broader one may allow others too.
+        if !existing.iter().any(|rule| granted(rule, &permission)) {
+            if allowed(existing, &permission) {
+                return Err(Error::BroaderRule(security_group_id).into());
+            }
+
+            debug!("Public ip is not allowed");
+            return Ok(Change::Unchanged);
+        }
+
+        let request = RevokeSecurityGroupIngressRequest {
+            group_id: Some(security_group_id.clone()),
+            ip_permissions: Some(vec![permission.clone()]),
+            dry_run: Some(self.dry_run),
+            ..Default::default()
+        };
+
+        match self.client.revoke_security_group_ingress(request).await {
+            Ok(_) => Ok(Change::Applied),
+            // Not found means someone else revoked the same ip between our check and our request.
+            Err(e) => rejected(aws_error_code(&e), "InvalidPermission.NotFound", permission)
+                .ok_or_else(|| classify(security_group_id, e))
+                .context("Failed to remove public ip from allowlist"),
+        }
+    }
 
-        self.client
-            .revoke_security_group_ingress(request)
+    /// The inbound rules of the security group
+    #[instrument(level = "debug", skip(self))]
+    async fn permissions(&self, security_group_id: String) -> Result<Vec<IpPermission>> {
+        let request = DescribeSecurityGroupsRequest {
+            group_ids: Some(vec![security_group_id.clone()]),
+            ..Default::default()
+        };
+
+        let result = self
+            .client
+            .describe_security_groups(request)
             .await
-            .context("Failed to remove public ip from allowlist")?;
+            .map_err(|e| classify(security_group_id, e))
+            .context("Failed to retrieve security group")?;
 
-        Ok(())
+        Ok(result
+            .security_groups
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|group| group.ip_permissions.unwrap_or_default())
+            .collect())
     }
 
     async fn get_ip_permission(&self, description: Option<String>) -> Result<IpPermission> {
         let ip = public_ip::addr()
             .await
             .context("Unable to determine public ip")?;
+        debug!(%ip, "Determined public ip");
 
         Ok(IpPermission {
             from_port: Some(22),
@@ -62,3 +153,204 @@ impl<'a> Handler<'a> {
         })
     }
 }
+
+/// Whether any of the existing rules already allows the permission's ip
+fn allowed(existing: impl IntoIterator<Item = IpPermission>, permission: &IpPermission) -> bool {
+    let cidr_ip = permission
+        .ip_ranges
+        .as_ref()
+        .and_then(|ranges| ranges.first())
+        .and_then(|range| range.cidr_ip.clone());
+
+    existing
+        .into_iter()
+        .filter(|existing| covers(existing, permission))
+        .flat_map(|existing| existing.ip_ranges.unwrap_or_default())
+        .any(|range| range.cidr_ip == cidr_ip)
+}
+
+/// Whether an existing rule is the one a grant of the permission adds: the same protocol and
+/// ports, for the permission's ip
+fn granted(existing: &IpPermission, permission: &IpPermission) -> bool {
+    existing.ip_protocol == permission.ip_protocol
+        && existing.from_port == permission.from_port
+        && existing.to_port == permission.to_port
+        && allowed(vec![existing.clone()], permission)
+}
+
+/// Whether an existing rule allows at least the protocol and ports of the permission. A rule
+/// for protocol `-1` allows all traffic, whatever its ports.
+fn covers(existing: &IpPermission, permission: &IpPermission) -> bool {
+    if existing.ip_protocol.as_deref() == Some("-1") {
+        return true;
+    }
+
+    existing.ip_protocol == permission.ip_protocol
+        && existing.from_port <= permission.from_port
+        && existing.to_port >= permission.to_port
+}
+
+/// The outcome of a grant or revoke AWS rejected with the given error code, when the rejection
+/// is not a failure: a dry run, or `unchanged`, the code meaning the group was already in the
+/// requested state.
+fn rejected(code: Option<&str>, unchanged: &str, permission: IpPermission) -> Option<Change> {
+    match code {
+        Some("DryRunOperation") => Some(Change::Planned(permission)),
+        Some(code) if code == unchanged => Some(Change::Unchanged),
+        _ => None,
+    }
+}
+
+/// Translate the failure modes we know how to explain into a typed error, passing everything
+/// else through untouched.
+fn classify<E>(security_group_id: String, err: RusotoError<E>) -> anyhow::Error
+where
+    E: StdError + Send + Sync + 'static,
+{
+    match known_error(aws_error_code(&err), &security_group_id) {
+        Some(error) => error.into(),
+        None => aws_error(err, format!("Security group {}", security_group_id)),
+    }
+}
+
+/// The typed error for an error code we know how to explain
+fn known_error(code: Option<&str>, security_group_id: &str) -> Option<Error> {
+    let security_group_id = security_group_id.to_string();
+
+    match code? {
+        "InvalidGroup.NotFound" | "InvalidGroupId.Malformed" => {
+            Some(Error::MissingGroup(security_group_id))
+        }
+        "UnauthorizedOperation" => Some(Error::PermissionDenied(security_group_id)),
+        "RulesPerSecurityGroupLimitExceeded" => Some(Error::RuleLimitReached(security_group_id)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{allowed, granted, known_error, rejected, Change, Error};
+    use rusoto_ec2::{IpPermission, IpRange};
+
+    fn rule(protocol: &str, from: i64, to: i64, cidr: &str) -> IpPermission {
+        IpPermission {
+            from_port: Some(from),
+            to_port: Some(to),
+            ip_protocol: Some(protocol.into()),
+            ip_ranges: Some(vec![IpRange {
+                cidr_ip: Some(cidr.into()),
+                description: None,
+            }]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn known_error_codes_are_typed() {
+        assert!(matches!(
+            known_error(Some("InvalidGroup.NotFound"), "sg-1"),
+            Some(Error::MissingGroup(id)) if id == "sg-1"
+        ));
+        assert!(matches!(
+            known_error(Some("InvalidGroupId.Malformed"), "sg-1"),
+            Some(Error::MissingGroup(_))
+        ));
+        assert!(matches!(
+            known_error(Some("UnauthorizedOperation"), "sg-1"),
+            Some(Error::PermissionDenied(_))
+        ));
+        assert!(matches!(
+            known_error(Some("RulesPerSecurityGroupLimitExceeded"), "sg-1"),
+            Some(Error::RuleLimitReached(_))
+        ));
+        assert!(known_error(Some("InternalError"), "sg-1").is_none());
+        assert!(known_error(None, "sg-1").is_none());
+    }
+
+    #[test]
+    fn dry_runs_and_rules_already_in_place_are_not_failures() {
+        let permission = rule("tcp", 22, 22, "10.0.0.1/32");
+
+        assert_eq!(
+            Some(Change::Unchanged),
+            rejected(
+                Some("InvalidPermission.Duplicate"),
+                "InvalidPermission.Duplicate",
+                permission.clone()
+            )
+        );
+        assert_eq!(
+            Some(Change::Planned(permission.clone())),
+            rejected(
+                Some("DryRunOperation"),
+                "InvalidPermission.Duplicate",
+                permission.clone()
+            )
+        );
+        assert_eq!(
+            None,
+            rejected(
+                Some("InvalidPermission.NotFound"),
+                "InvalidPermission.Duplicate",
+                permission.clone()
+            )
+        );
+        assert_eq!(
+            None,
+            rejected(None, "InvalidPermission.Duplicate", permission)
+        );
+    }
+
+    #[test]
+    fn rules_allowing_the_ip_and_port_match() {
+        let permission = rule("tcp", 22, 22, "10.0.0.1/32");
+
+        assert!(allowed(
+            vec![rule("tcp", 22, 22, "10.0.0.1/32")],
+            &permission
+        ));
+        assert!(allowed(
+            vec![rule("tcp", 0, 1024, "10.0.0.1/32")],
+            &permission
+        ));
+        assert!(allowed(
+            vec![rule("-1", -1, -1, "10.0.0.1/32")],
+            &permission
+        ));
+        assert!(allowed(
+            vec![
+                rule("tcp", 22, 22, "10.0.0.2/32"),
+                rule("tcp", 22, 22, "10.0.0.1/32")
+            ],
+            &permission
+        ));
+
+        assert!(!allowed(
+            vec![rule("udp", 22, 22, "10.0.0.1/32")],
+            &permission
+        ));
+        assert!(!allowed(
+            vec![rule("tcp", 80, 443, "10.0.0.1/32")],
+            &permission
+        ));
+        assert!(!allowed(
+            vec![rule("tcp", 22, 22, "10.0.0.2/32")],
+            &permission
+        ));
+        assert!(!allowed(vec![], &permission));
+    }
+
+    #[test]
+    fn only_the_exact_rule_counts_as_granted() {
+        let permission = rule("tcp", 22, 22, "10.0.0.1/32");
+
+        assert!(granted(&rule("tcp", 22, 22, "10.0.0.1/32"), &permission));
+
+        // These allow the ip too, but are not what a grant adds, so a revoke leaves them alone
+        let all_traffic = rule("-1", -1, -1, "10.0.0.1/32");
+        assert!(allowed(vec![all_traffic.clone()], &permission));
+        assert!(!granted(&all_traffic, &permission));
+        assert!(!granted(&rule("tcp", 0, 1024, "10.0.0.1/32"), &permission));
+        assert!(!granted(&rule("tcp", 22, 22, "10.0.0.2/32"), &permission));
+    }
+}
@@ -0,0 +1,138 @@
+use chrono::{DateTime, Utc};
+use rusoto_credential::{AwsCredentials, CredentialsError};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    /// Describes where the credentials came from, so a changed profile invalidates the entry
+    key: String,
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    expiration: DateTime<Utc>,
+}
+
+/// Load cached session credentials for a profile, if they are present and still valid
+pub fn load(profile: &str, key: &str) -> Option<AwsCredentials> {
+    load_from(&path(profile)?, key)
+}
+
+fn load_from(path: &Path, key: &str) -> Option<AwsCredentials> {
+    let contents = fs::read(path).ok()?;
+    let entry: Entry = serde_json::from_slice(&contents).ok()?;
+
+    if entry.key != key {
+        return None;
+    }
+
+    let credentials = AwsCredentials::new(
+        entry.access_key_id,
+        entry.secret_access_key,
+        entry.session_token,
+        Some(entry.expiration),
+    );
+
+    if super::expiring(&credentials) {
+        return None;
+    }
+
+    Some(credentials)
+}
+
+/// Cache session credentials for a profile. Credentials without an expiration are long lived and
+/// are never written to the cache.
+pub fn store(
+    profile: &str,
+    key: &str,
+    credentials: &AwsCredentials,
+) -> Result<(), CredentialsError> {
+    match path(profile) {
+        Some(path) => store_to(&path, key, credentials),
+        None => Ok(()),
+    }
+}
+
+fn store_to(path: &Path, key: &str, credentials: &AwsCredentials) -> Result<(), CredentialsError> {
+    let expiration = match credentials.expires_at() {
+        Some(expiration) => *expiration,
+        None => return Ok(()),
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let entry = Entry {
+        key: key.into(),
+        access_key_id: credentials.aws_access_key_id().into(),
+        secret_access_key: credentials.aws_secret_access_key().into(),
+        session_token: credentials.token().clone(),
+        expiration,
+    };
+
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(&serde_json::to_vec(&entry)?)?;
+
+    Ok(())
+}
+
+fn path(profile: &str) -> Option<PathBuf> {
+    let mut path = dirs::cache_dir()?;
+    path.push("heimdallr");
+    path.push("credentials");
+    path.push(format!("{}.json", profile));
+
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_from, store_to};
+    use chrono::{Duration, Utc};
+    use rusoto_credential::AwsCredentials;
+
+    #[test]
+    fn cached_credentials_are_loaded_until_they_expire() {
+        let path =
+            std::env::temp_dir().join(format!("heimdallr-credentials-{}.json", std::process::id()));
+        let valid = AwsCredentials::new(
+            "key",
+            "secret",
+            Some("token".into()),
+            Some(Utc::now() + Duration::hours(1)),
+        );
+
+        store_to(&path, "sso url", &valid).unwrap();
+        let loaded = load_from(&path, "sso url").unwrap();
+        assert_eq!("key", loaded.aws_access_key_id());
+        assert_eq!(&Some("token".to_string()), loaded.token());
+
+        // A changed profile invalidates the entry
+        assert!(load_from(&path, "assume-role base arn").is_none());
+
+        let expiring = AwsCredentials::new(
+            "key",
+            "secret",
+            None,
+            Some(Utc::now() + Duration::minutes(1)),
+        );
+        store_to(&path, "sso url", &expiring).unwrap();
+        assert!(load_from(&path, "sso url").is_none());
+
+        // Long lived credentials are never cached, leaving the previous entry in place
+        let static_keys = AwsCredentials::new("static", "secret", None, None);
+        store_to(&path, "static", &static_keys).unwrap();
+        assert!(load_from(&path, "static").is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,536 @@
+use crate::settings;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use dialoguer::Input;
+use ini::Ini;
+use rusoto_core::{region::Region, HttpClient};
+use rusoto_credential::{
+    AwsCredentials, CredentialsError, EnvironmentProvider, ProfileProvider, ProvideAwsCredentials,
+};
+use rusoto_sts::{StsAssumeRoleSessionCredentialsProvider, StsClient};
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+mod cache;
+mod sso;
+
+/// Credentials this close to expiring are treated as already expired
+const EXPIRY_MARGIN_MINUTES: i64 = 5;
+
+/// Supplies AWS credentials for a heimdallr profile.
+///
+/// Credentials are resolved on first use, so commands which never talk to AWS never prompt for an
+/// MFA code. Session tokens from assumed roles and SSO are cached on disk until they expire.
+#[derive(Clone)]
+pub struct Provider {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    name: String,
+    aws_profile: String,
+    region: Region,
+    source: settings::Credentials,
+    current: Mutex<Option<AwsCredentials>>,
+}
+
+impl Provider {
+    pub fn new(name: &str, profile: &settings::Profile, region: Region) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                name: name.into(),
+                aws_profile: profile.aws_profile.clone(),
+                region,
+                source: profile.credentials.clone(),
+                current: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Credentials for a profile in your AWS configuration, for use before a heimdallr profile
+    /// exists
+    pub fn for_aws_profile(aws_profile: &str, region: Region) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                name: aws_profile.into(),
+                aws_profile: aws_profile.into(),
+                region,
+                source: settings::Credentials::Profile,
+                current: Mutex::new(None),
+            }),
+        }
+    }
+
+    async fn resolve(&self) -> Result<AwsCredentials, CredentialsError> {
+        let inner = &self.inner;
+
+        match &inner.source {
+            settings::Credentials::Environment => {
+                EnvironmentProvider::default().credentials().await
+            }
+            settings::Credentials::Profile => self.resolve_aws_profile().await,
+            settings::Credentials::AssumeRole {
+                role_arn,
+                source_profile,
+                mfa_serial,
+                external_id,
+                duration_seconds,
+            } => {
+                self.assume_role(
+                    source_profile.as_deref().unwrap_or(&inner.aws_profile),
+                    role_arn,
+                    mfa_serial.clone(),
+                    external_id.clone(),
+                    *duration_seconds,
+                )
+                .await
+            }
+            settings::Credentials::Sso {
+                start_url,
+                region,
+                account_id,
+                role_name,
+            } => {
+                let region = match region {
+                    Some(region) => parse_region(region)?,
+                    None => inner.region.clone(),
+                };
+                self.sso(start_url, region, account_id, role_name).await
+            }
+            settings::Credentials::Process { command } => process(command).await,
+        }
+    }
+
+    /// Mirror what the AWS cli does with a named profile, which rusoto's ProfileProvider only
+    /// partially supports.
+    async fn resolve_aws_profile(&self) -> Result<AwsCredentials, CredentialsError> {
+        let inner = &self.inner;
+        let config = aws_config_profile(&inner.aws_profile)?;
+
+        match aws_profile_source(&inner.aws_profile, &config)? {
+            AwsProfileSource::AssumeRole {
+                role_arn,
+                source_profile,
+                mfa_serial,
+                external_id,
+                duration_seconds,
+            } => {
+                self.assume_role(
+                    &source_profile,
+                    &role_arn,
+                    mfa_serial,
+                    external_id,
+                    duration_seconds,
+                )
+                .await
+            }
+            AwsProfileSource::Sso {
+                account_id,
+                role_name,
+                session,
+            } => {
+                let session = match session {
+                    Some(session) => aws_config_section(&format!("sso-session {}", session))?,
+                    None => config,
+                };
+
+                let start_url = session.get("sso_start_url").ok_or_else(|| {
+                    CredentialsError::new(format!(
+                        "AWS profile {} is missing sso_start_url",
+                        inner.aws_profile
+                    ))
+                })?;
+                let region = match session.get("sso_region") {
+                    Some(region) => parse_region(region)?,
+                    None => inner.region.clone(),
+                };
+
+                self.sso(start_url, region, &account_id, &role_name).await
+            }
+            // Static keys and credential_process are both handled by rusoto
+            AwsProfileSource::Keys => {
+                let mut provider = ProfileProvider::new()?;
+                provider.set_profile(inner.aws_profile.clone());
+                provider.credentials().await
+            }
+        }
+    }
+
+    async fn assume_role(
+        &self,
+        source_profile: &str,
+        role_arn: &str,
+        mfa_serial: Option<String>,
+        external_id: Option<String>,
+        duration_seconds: Option<i64>,
+    ) -> Result<AwsCredentials, CredentialsError> {
+        let key = format!("assume-role {} {}", source_profile, role_arn);
+        if let Some(credentials) = cache::load(&self.inner.name, &key) {
+            return Ok(credentials);
+        }
+
+        let mut source = ProfileProvider::new()?;
+        source.set_profile(source_profile);
+
+        let client = StsClient::new_with(
+            HttpClient::new().map_err(CredentialsError::new)?,
+            source,
+            self.inner.region.clone(),
+        );
+
+        let mut provider = StsAssumeRoleSessionCredentialsProvider::new(
+            client,
+            role_arn.into(),
+            "heimdallr".into(),
+            external_id,
+            duration_seconds.map(Duration::seconds),
+            None,
+            mfa_serial.clone(),
+        );
+
+        if let Some(serial) = mfa_serial {
+            provider.set_mfa_code(prompt_mfa_code(serial).await?);
+        }
+
+        let credentials = provider.credentials().await?;
+        cache::store(&self.inner.name, &key, &credentials)?;
+
+        Ok(credentials)
+    }
+
+    async fn sso(
+        &self,
+        start_url: &str,
+        region: Region,
+        account_id: &str,
+        role_name: &str,
+    ) -> Result<AwsCredentials, CredentialsError> {
+        let key = format!("sso {} {} {}", start_url, account_id, role_name);
+        if let Some(credentials) = cache::load(&self.inner.name, &key) {
+            return Ok(credentials);
+        }
+
+        let credentials = sso::credentials(start_url, region, account_id, role_name).await?;
+        cache::store(&self.inner.name, &key, &credentials)?;
+
+        Ok(credentials)
+    }
+}
+
+#[async_trait]
+impl ProvideAwsCredentials for Provider {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        // Holding the lock while resolving ensures concurrent requests share a single MFA prompt
+        let mut current = self.inner.current.lock().await;
+
+        if let Some(credentials) = current.as_ref() {
+            if !expiring(credentials) {
+                return Ok(credentials.clone());
+            }
+        }
+
+        let credentials = self.resolve().await?;
+        *current = Some(credentials.clone());
+
+        Ok(credentials)
+    }
+}
+
+/// Where a profile in your AWS config gets its credentials from
+#[derive(Debug, PartialEq)]
+enum AwsProfileSource {
+    AssumeRole {
+        role_arn: String,
+        source_profile: String,
+        mfa_serial: Option<String>,
+        external_id: Option<String>,
+        duration_seconds: Option<i64>,
+    },
+    Sso {
+        account_id: String,
+        role_name: String,
+        /// The sso-session section holding the start url and region, when they are not set on the
+        /// profile itself
+        session: Option<String>,
+    },
+    Keys,
+}
+
+/// Pick the credential source for a profile the way the AWS cli does: an assumed role wins over
+/// SSO, which wins over static keys and credential_process.
+fn aws_profile_source(
+    aws_profile: &str,
+    config: &HashMap<String, String>,
+) -> Result<AwsProfileSource, CredentialsError> {
+    if let Some(role_arn) = config.get("role_arn") {
+        let source_profile = config.get("source_profile").ok_or_else(|| {
+            CredentialsError::new(format!(
+                "AWS profile {} sets role_arn without a source_profile",
+                aws_profile
+            ))
+        })?;
+
+        return Ok(AwsProfileSource::AssumeRole {
+            role_arn: role_arn.clone(),
+            source_profile: source_profile.clone(),
+            mfa_serial: config.get("mfa_serial").cloned(),
+            external_id: config.get("external_id").cloned(),
+            duration_seconds: config
+                .get("duration_seconds")
+                .and_then(|seconds| seconds.parse().ok()),
+        });
+    }
+
+    if let (Some(account_id), Some(role_name)) =
+        (config.get("sso_account_id"), config.get("sso_role_name"))
+    {
+        return Ok(AwsProfileSource::Sso {
+            account_id: account_id.clone(),
+            role_name: role_name.clone(),
+            session: config.get("sso_session").cloned(),
+        });
+    }
+
+    Ok(AwsProfileSource::Keys)
+}
+
+fn expiring(credentials: &AwsCredentials) -> bool {
+    match credentials.expires_at() {
+        Some(expires_at) => *expires_at <= Utc::now() + Duration::minutes(EXPIRY_MARGIN_MINUTES),
+        None => false,
+    }
+}
+
+fn parse_region(region: &str) -> Result<Region, CredentialsError> {
+    region
+        .parse::<Region>()
+        .map_err(|e| CredentialsError::new(format!("Invalid region {}: {}", region, e)))
+}
+
+async fn prompt_mfa_code(serial: String) -> Result<String, CredentialsError> {
+    tokio::task::spawn_blocking(move || {
+        Input::<String>::new()
+            .with_prompt(format!("MFA code for {}", serial))
+            .interact_text()
+    })
+    .await
+    .map_err(CredentialsError::new)?
+    .map_err(CredentialsError::from)
+}
+
+async fn process(command: &str) -> Result<AwsCredentials, CredentialsError> {
+    let output = Command::new("sh").arg("-c").arg(command).output().await?;
+
+    if !output.status.success() {
+        return Err(CredentialsError::new(format!(
+            "Credential process `{}` failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Names of the profiles defined in your AWS config and credentials files
+pub fn aws_profile_names() -> Vec<String> {
+    let mut names: Vec<String> = [aws_config_path(), aws_credentials_path()]
+        .iter()
+        .flatten()
+        .filter_map(|path| Ini::load_from_file(path).ok())
+        .flat_map(|config| {
+            config
+                .sections()
+                .flatten()
+                .map(|section| section.trim_start_matches("profile ").to_string())
+                .filter(|section| !section.starts_with("sso-session "))
+                .collect::<Vec<String>>()
+        })
+        .collect();
+
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// The default region configured for a profile in your AWS config file
+pub fn aws_profile_region(profile: &str) -> Option<String> {
+    aws_config_profile(profile).ok()?.get("region").cloned()
+}
+
+fn aws_config_profile(profile: &str) -> Result<HashMap<String, String>, CredentialsError> {
+    if profile == "default" {
+        aws_config_section("default")
+    } else {
+        aws_config_section(&format!("profile {}", profile))
+    }
+}
+
+fn aws_config_section(section: &str) -> Result<HashMap<String, String>, CredentialsError> {
+    let path = match aws_config_path() {
+        Some(path) => path,
+        None => return Ok(HashMap::new()),
+    };
+
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let config = Ini::load_from_file(&path)
+        .map_err(|e| CredentialsError::new(format!("Unable to read {}: {}", path.display(), e)))?;
+
+    Ok(config
+        .section(Some(section))
+        .map(|properties| {
+            properties
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect()
+        })
+        .unwrap_or_default())
+}
+
+fn aws_config_path() -> Option<PathBuf> {
+    match env::var_os("AWS_CONFIG_FILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(dirs::home_dir()?.join(".aws").join("config")),
+    }
+}
+
+fn aws_credentials_path() -> Option<PathBuf> {
+    match env::var_os("AWS_SHARED_CREDENTIALS_FILE") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => Some(dirs::home_dir()?.join(".aws").join("credentials")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{aws_profile_source, expiring, process, AwsProfileSource, Inner, Provider};
+    use crate::settings;
+    use chrono::{Duration, Utc};
+    use rusoto_core::Region;
+    use rusoto_credential::{AwsCredentials, ProvideAwsCredentials};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::Mutex;
+
+    const FRESH: &str = r#"printf '{"Version":1,"AccessKeyId":"fresh","SecretAccessKey":"secret","SessionToken":"token","Expiration":"2099-01-01T00:00:00Z"}'"#;
+
+    fn config(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn credentials(key: &str, expires_in: Duration) -> AwsCredentials {
+        AwsCredentials::new(key, "secret", None, Some(Utc::now() + expires_in))
+    }
+
+    fn process_provider(command: &str, current: Option<AwsCredentials>) -> Provider {
+        Provider {
+            inner: Arc::new(Inner {
+                name: "test".into(),
+                aws_profile: "test".into(),
+                region: Region::UsEast1,
+                source: settings::Credentials::Process {
+                    command: command.into(),
+                },
+                current: Mutex::new(current),
+            }),
+        }
+    }
+
+    #[test]
+    fn assumed_roles_win_over_sso_and_sso_over_keys() {
+        let both = config(&[
+            ("role_arn", "arn:aws:iam::123456789012:role/admin"),
+            ("source_profile", "base"),
+            ("mfa_serial", "arn:aws:iam::123456789012:mfa/me"),
+            ("duration_seconds", "900"),
+            ("sso_account_id", "123456789012"),
+            ("sso_role_name", "admin"),
+        ]);
+        assert_eq!(
+            AwsProfileSource::AssumeRole {
+                role_arn: "arn:aws:iam::123456789012:role/admin".into(),
+                source_profile: "base".into(),
+                mfa_serial: Some("arn:aws:iam::123456789012:mfa/me".into()),
+                external_id: None,
+                duration_seconds: Some(900),
+            },
+            aws_profile_source("both", &both).unwrap()
+        );
+
+        let sso = config(&[
+            ("sso_account_id", "123456789012"),
+            ("sso_role_name", "admin"),
+            ("sso_session", "work"),
+            ("aws_access_key_id", "AKIA"),
+        ]);
+        assert_eq!(
+            AwsProfileSource::Sso {
+                account_id: "123456789012".into(),
+                role_name: "admin".into(),
+                session: Some("work".into()),
+            },
+            aws_profile_source("sso", &sso).unwrap()
+        );
+
+        let keys = config(&[("aws_access_key_id", "AKIA"), ("sso_role_name", "admin")]);
+        assert_eq!(
+            AwsProfileSource::Keys,
+            aws_profile_source("keys", &keys).unwrap()
+        );
+
+        let orphan = config(&[("role_arn", "arn:aws:iam::123456789012:role/admin")]);
+        assert!(aws_profile_source("orphan", &orphan).is_err());
+    }
+
+    #[test]
+    fn credentials_close_to_expiring_are_expired() {
+        assert!(expiring(&credentials("old", Duration::minutes(-1))));
+        assert!(expiring(&credentials("soon", Duration::minutes(2))));
+        assert!(!expiring(&credentials("later", Duration::hours(1))));
+        assert!(!expiring(&AwsCredentials::new(
+            "static", "secret", None, None
+        )));
+    }
+
+    #[tokio::test]
+    async fn expiring_credentials_are_refreshed() {
+        let current = credentials("stale", Duration::minutes(1));
+        let provider = process_provider(FRESH, Some(current));
+        assert_eq!(
+            "fresh",
+            provider.credentials().await.unwrap().aws_access_key_id()
+        );
+
+        // Valid credentials are reused without running the process again
+        let current = credentials("current", Duration::hours(1));
+        let provider = process_provider("exit 1", Some(current));
+        assert_eq!(
+            "current",
+            provider.credentials().await.unwrap().aws_access_key_id()
+        );
+    }
+
+    #[tokio::test]
+    async fn process_output_is_parsed() {
+        let credentials = process(FRESH).await.unwrap();
+        assert_eq!("fresh", credentials.aws_access_key_id());
+        assert_eq!("secret", credentials.aws_secret_access_key());
+        assert_eq!(&Some("token".to_string()), credentials.token());
+        assert!(credentials.expires_at().is_some());
+
+        let error = process("echo denied >&2; exit 1").await.unwrap_err();
+        assert!(error.to_string().contains("denied"));
+
+        assert!(process("echo not json").await.is_err());
+    }
+}
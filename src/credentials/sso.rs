@@ -0,0 +1,145 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rusoto_core::request::DispatchSignedRequest;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{region::Region, HttpClient};
+use rusoto_credential::{AwsCredentials, CredentialsError};
+use serde::Deserialize;
+use std::fs;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedToken {
+    start_url: Option<String>,
+    access_token: String,
+    expires_at: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GetRoleCredentialsResponse {
+    role_credentials: RoleCredentials,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: i64,
+}
+
+/// Exchange the access token cached by `aws sso login` for credentials to the given role
+pub async fn credentials(
+    start_url: &str,
+    region: Region,
+    account_id: &str,
+    role_name: &str,
+) -> Result<AwsCredentials, CredentialsError> {
+    let token = cached_token(start_url)?;
+
+    let mut request = SignedRequest::new("GET", "portal.sso", &region, "/federation/credentials");
+    request.set_hostname(Some(format!("portal.sso.{}.amazonaws.com", region.name())));
+    request.add_param("account_id", account_id);
+    request.add_param("role_name", role_name);
+    request.add_header("x-amz-sso_bearer_token", &token);
+    request.complement();
+
+    let client = HttpClient::new().map_err(CredentialsError::new)?;
+    let response = client
+        .dispatch(request, None)
+        .await
+        .map_err(CredentialsError::new)?
+        .buffer()
+        .await
+        .map_err(CredentialsError::new)?;
+
+    if !response.status.is_success() {
+        return Err(CredentialsError::new(format!(
+            "Unable to retrieve SSO credentials for {} in account {} ({}): {}",
+            role_name,
+            account_id,
+            response.status,
+            response.body_as_str()
+        )));
+    }
+
+    let credentials: GetRoleCredentialsResponse = serde_json::from_slice(&response.body)?;
+    let credentials = credentials.role_credentials;
+
+    Ok(AwsCredentials::new(
+        credentials.access_key_id,
+        credentials.secret_access_key,
+        Some(credentials.session_token),
+        Some(Utc.timestamp_millis(credentials.expiration)),
+    ))
+}
+
+fn cached_token(start_url: &str) -> Result<String, CredentialsError> {
+    let not_logged_in = || {
+        CredentialsError::new(format!(
+            "No valid SSO session for {}. Run `aws sso login` and try again.",
+            start_url
+        ))
+    };
+
+    let mut dir = dirs::home_dir().ok_or_else(not_logged_in)?;
+    dir.push(".aws");
+    dir.push("sso");
+    dir.push("cache");
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Err(not_logged_in()),
+    };
+
+    for entry in entries.flatten() {
+        let token: CachedToken = match fs::read(entry.path())
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+        {
+            Some(token) => token,
+            None => continue,
+        };
+
+        if token.start_url.as_deref() != Some(start_url) {
+            continue;
+        }
+
+        if let Some(expires_at) = parse_expiry(&token.expires_at) {
+            if expires_at > Utc::now() {
+                return Ok(token.access_token);
+            }
+        }
+    }
+
+    Err(not_logged_in())
+}
+
+/// Older versions of the AWS cli write expiry times as 2021-05-11T12:00:00UTC rather than RFC
+/// 3339.
+fn parse_expiry(expires_at: &str) -> Option<DateTime<Utc>> {
+    let normalized = match expires_at.strip_suffix("UTC") {
+        Some(prefix) => format!("{}Z", prefix),
+        None => expires_at.to_string(),
+    };
+
+    DateTime::parse_from_rfc3339(&normalized)
+        .ok()
+        .map(|expires_at| expires_at.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_expiry;
+
+    #[test]
+    fn parse_expiry_supports_both_cli_formats() {
+        assert_eq!(
+            parse_expiry("2021-05-11T12:00:00Z"),
+            parse_expiry("2021-05-11T12:00:00UTC")
+        );
+        assert!(parse_expiry("2021-05-11T12:00:00Z").is_some());
+        assert!(parse_expiry("yesterday").is_none());
+    }
+}
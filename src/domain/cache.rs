@@ -0,0 +1,167 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    fetched_at: DateTime<Utc>,
+    value: serde_json::Value,
+}
+
+/// Inventory looked up in AWS, kept on disk for a while so repeated commands skip the lookups.
+/// Every lookup is stored under a key in a single file per profile and region.
+pub struct Cache {
+    path: Option<PathBuf>,
+    ttl: Duration,
+    /// Ignore cached entries, replacing them with whatever is stored next
+    refresh: bool,
+}
+
+impl Cache {
+    pub fn new(path: impl Into<PathBuf>, ttl_seconds: u64, refresh: bool) -> Self {
+        Self {
+            path: Some(path.into()),
+            ttl: Duration::seconds(ttl_seconds as i64),
+            refresh,
+        }
+    }
+
+    /// The cache for a profile and region in the user's cache directory
+    pub fn managed(profile: &str, region: &str, ttl_seconds: u64, refresh: bool) -> Self {
+        match dirs::cache_dir() {
+            Some(dir) => Self::new(
+                dir.join("heimdallr")
+                    .join("inventory")
+                    .join(format!("{}-{}.json", profile, region)),
+                ttl_seconds,
+                refresh,
+            ),
+            None => Self::disabled(),
+        }
+    }
+
+    /// A cache which never holds anything
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            ttl: Duration::zero(),
+            refresh: true,
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.path.is_some() && self.ttl > Duration::zero()
+    }
+
+    /// The value stored under the key, unless it is missing or older than the ttl
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        if !self.enabled() || self.refresh {
+            return None;
+        }
+
+        let entry = self.entries().remove(key)?;
+        if Utc::now() - entry.fetched_at > self.ttl {
+            return None;
+        }
+
+        serde_json::from_value(entry.value).ok()
+    }
+
+    pub fn put<T: Serialize>(&self, key: &str, value: &T) -> io::Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        let value = serde_json::to_value(value)?;
+        self.update(|entries| {
+            entries.insert(
+                key.into(),
+                Entry {
+                    fetched_at: Utc::now(),
+                    value,
+                },
+            );
+        })
+    }
+
+    pub fn remove(&self, key: &str) -> io::Result<()> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        self.update(|entries| {
+            entries.remove(key);
+        })
+    }
+
+    fn entries(&self) -> HashMap<String, Entry> {
+        self.path
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    fn update(&self, change: impl FnOnce(&mut HashMap<String, Entry>)) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let mut entries = self.entries();
+        change(&mut entries);
+
+        // Drop whatever expired, so lookups which are never repeated do not pile up
+        let now = Utc::now();
+        entries.retain(|_, entry| now - entry.fetched_at <= self.ttl);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Concurrent commands each write their own file, and replace the cache with it whole, so a
+        // reader never sees a partly written cache
+        let written = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&written, serde_json::to_vec(&entries)?)?;
+        fs::rename(&written, path).inspect_err(|_| {
+            let _ = fs::remove_file(&written);
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use std::fs;
+
+    #[test]
+    fn entries_are_used_until_refreshed_or_disabled() {
+        let path = std::env::temp_dir().join(format!("heimdallr-inventory-{}", std::process::id()));
+
+        let cache = Cache::new(&path, 60, false);
+        cache.put("names", &vec!["api", "worker"]).unwrap();
+        assert_eq!(
+            Some(vec!["api".to_string(), "worker".into()]),
+            cache.get("names")
+        );
+        assert_eq!(None, cache.get::<Vec<String>>("instances"));
+
+        assert_eq!(
+            None,
+            Cache::new(&path, 60, true).get::<Vec<String>>("names")
+        );
+        assert_eq!(
+            None,
+            Cache::new(&path, 0, false).get::<Vec<String>>("names")
+        );
+
+        cache.remove("names").unwrap();
+        assert_eq!(None, cache.get::<Vec<String>>("names"));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
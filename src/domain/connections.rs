@@ -1,3 +1,8 @@
+use super::known_hosts::HostKeyChecking;
+use crate::keys::signer::Certificate;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 
@@ -5,6 +10,16 @@ use std::fmt;
 pub struct Container {
     pub name: String,
     pub runtime_id: String,
+    pub health_status: Option<String>,
+}
+
+/// Details of the ecs task a connection lands in
+#[derive(Clone, Debug, Default)]
+pub struct Task {
+    /// The task definition family and revision, e.g. api:12
+    pub task_definition: Option<String>,
+    pub health_status: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
 }
 
 pub struct Connections {
@@ -36,9 +51,8 @@ impl Connections {
 
     pub fn container_arns(&self) -> Vec<String> {
         self.connections
-            .clone()
-            .into_iter()
-            .filter_map(|(_, connection)| connection.container_instance_id)
+            .values()
+            .filter_map(|connection| connection.container_instance_id.clone())
             .collect()
     }
 
@@ -49,10 +63,14 @@ impl Connections {
             .add_container(container);
     }
 
+    pub fn set_task(&mut self, task_id: String, task: Task) {
+        self.connections.get_mut(&task_id).unwrap().set_task(task);
+    }
+
     pub fn set_container_instance_id(&mut self, task_id: String, container_instance_id: String) {
         self.container_instance_id_to_task_id_map
             .entry(container_instance_id.clone())
-            .or_insert_with(Vec::new)
+            .or_default()
             .push(task_id.clone());
         self.connections
             .get_mut(&task_id)
@@ -77,7 +95,13 @@ impl Connections {
         }
     }
 
-    pub fn set_name_and_ip(&mut self, ec2_instance_id: String, name: String, ip: String) {
+    pub fn set_name_and_ip(
+        &mut self,
+        ec2_instance_id: String,
+        name: String,
+        ip: String,
+        availability_zone: String,
+    ) {
         let task_ids = self
             .instance_id_to_task_id_map
             .get(&ec2_instance_id)
@@ -87,23 +111,25 @@ impl Connections {
             self.connections
                 .get_mut(&task_id.clone())
                 .unwrap()
-                .set_name_and_ip(name.clone(), ip.clone());
+                .set_name_and_ip(name.clone(), ip.clone(), availability_zone.clone());
         }
     }
 
     pub fn get_connections(&self) -> Vec<Connection> {
-        self.connections
-            .clone()
+        self.connections.values().cloned().collect()
+    }
+
+    pub fn get_connection_choices(&self) -> Vec<Box<dyn SshConnection>> {
+        self.container_choices()
             .into_iter()
-            .map(|(_, connection)| connection)
+            .map(|choice| Box::new(choice) as Box<dyn SshConnection>)
             .collect()
     }
 
-    pub fn get_connection_choices(&self) -> Vec<Box<dyn SshConnection>> {
+    pub fn container_choices(&self) -> Vec<ContainerChoice> {
         self.connections
-            .clone()
-            .into_iter()
-            .flat_map(|(_, connection)| connection.get_connection_choices())
+            .iter()
+            .flat_map(|(task_id, connection)| connection.container_choices(task_id))
             .collect()
     }
 }
@@ -115,6 +141,8 @@ pub struct Connection {
     instance_id: Option<String>,
     instance_name: Option<String>,
     private_ip: Option<String>,
+    availability_zone: Option<String>,
+    task: Task,
 }
 
 impl Connection {
@@ -125,6 +153,8 @@ impl Connection {
             instance_id: None,
             instance_name: None,
             private_ip: None,
+            availability_zone: None,
+            task: Task::default(),
         }
     }
 
@@ -136,6 +166,10 @@ impl Connection {
         self.containers.push(container);
     }
 
+    fn set_task(&mut self, task: Task) {
+        self.task = task;
+    }
+
     fn set_container_instance_id(&mut self, container_instance_id: String) {
         self.container_instance_id = Some(container_instance_id);
     }
@@ -144,45 +178,333 @@ impl Connection {
         self.instance_id = Some(instance_id);
     }
 
-    fn set_name_and_ip(&mut self, name: String, ip: String) {
+    fn set_name_and_ip(&mut self, name: String, ip: String, availability_zone: String) {
         self.instance_name = Some(name);
         self.private_ip = Some(ip);
+        self.availability_zone = Some(availability_zone);
     }
 
-    fn get_connection_choices(&self) -> Vec<Box<dyn SshConnection>> {
+    fn container_choices(&self, task_id: &str) -> Vec<ContainerChoice> {
         self.containers
             .iter()
-            .map(|container| {
-                Box::new(ContainerChoice {
-                    instance_id: self.instance_id.clone().unwrap(),
-                    instance_name: self.instance_name.clone().unwrap(),
-                    private_ip: self.private_ip.clone().unwrap(),
-                    name: container.name.clone(),
-                    runtime_id: container.runtime_id.clone(),
-                }) as Box<dyn SshConnection>
+            .map(|container| ContainerChoice {
+                instance_id: self.instance_id.clone().unwrap(),
+                instance_name: self.instance_name.clone().unwrap(),
+                private_ip: self.private_ip.clone().unwrap(),
+                availability_zone: self.availability_zone.clone().unwrap(),
+                task_id: task_id.into(),
+                task_definition: self.task.task_definition.clone(),
+                health_status: container
+                    .health_status
+                    .clone()
+                    .or_else(|| self.task.health_status.clone()),
+                started_at: self.task.started_at,
+                name: container.name.clone(),
+                runtime_id: container.runtime_id.clone(),
             })
             .collect()
     }
 }
 
+/// How ssh reaches the instance through the bastion
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    /// Log in to the bastion and run ssh there to reach the instance, forwarding your agent so the
+    /// second hop can use your keys
+    #[default]
+    Bastion,
+    /// Run the bastion hop locally as a ProxyCommand, so both hops authenticate with local keys
+    /// and host keys are checked against the local known hosts file
+    ProxyCommand,
+}
+
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Topology::Bastion => write!(f, "bastion"),
+            Topology::ProxyCommand => write!(f, "proxy_command"),
+        }
+    }
+}
+
+/// The bastion and instance settings used to build an ssh invocation
+#[derive(Clone, Debug)]
+pub struct SshSettings {
+    pub dns_name: String,
+    pub bastion_port: u16,
+    pub bastion_user: String,
+    pub ec2_user: String,
+
+    /// Identity files offered to the bastion server. When empty, ssh falls back to its own
+    /// configuration and ssh-agent.
+    pub bastion_identity_files: Vec<String>,
+
+    /// Identity files offered to the instance. When empty, ssh falls back to its own
+    /// configuration and ssh-agent. Only used by the proxy command topology.
+    pub ec2_identity_files: Vec<String>,
+
+    pub topology: Topology,
+
+    /// Only offer the identity files given, even if ssh-agent holds other keys
+    pub identities_only: bool,
+
+    /// The known hosts file both hops record and check host keys against
+    pub known_hosts_file: String,
+
+    pub host_key_checking: HostKeyChecking,
+
+    /// Push an ephemeral key to both hops with EC2 Instance Connect before connecting
+    pub instance_connect: bool,
+
+    /// Have an ephemeral key signed before connecting, authenticating with its certificate
+    pub certificate: Option<Certificate>,
+
+    /// The certificate offered by both hops alongside their identity files
+    pub certificate_file: Option<String>,
+}
+
+impl SshSettings {
+    /// Settings authenticating both hops with only the given key, and its certificate if any
+    pub fn with_session_key(&self, identity_file: &str, certificate_file: Option<&str>) -> Self {
+        Self {
+            bastion_identity_files: vec![identity_file.into()],
+            ec2_identity_files: vec![identity_file.into()],
+            identities_only: true,
+            certificate_file: certificate_file.map(String::from),
+            ..self.clone()
+        }
+    }
+
+    /// Every identity file used by either hop
+    pub fn identity_files(&self) -> impl Iterator<Item = &String> {
+        self.bastion_identity_files
+            .iter()
+            .chain(self.ec2_identity_files.iter())
+    }
+
+    /// The setting which needs both hops to run locally, if any is in use while the topology runs
+    /// the second hop on the bastion, where local keys and host keys are out of reach
+    pub fn needs_proxy_command(&self) -> Option<&'static str> {
+        if self.topology == Topology::ProxyCommand {
+            return None;
+        }
+
+        // A shared identity_file authenticates the bastion hop, and the forwarded agent the
+        // instance hop. Only keys meant for the instance alone would go unused.
+        let instance_keys = !self.ec2_identity_files.is_empty()
+            && self.ec2_identity_files != self.bastion_identity_files;
+
+        if self.instance_connect {
+            Some("instance_connect")
+        } else if self.certificate.is_some() {
+            Some("certificate_signer")
+        } else if instance_keys {
+            Some("ec2_identity_file")
+        } else if self.host_key_checking == HostKeyChecking::Console {
+            Some("host_key_checking = \"console\"")
+        } else {
+            None
+        }
+    }
+
+    /// Build an ssh invocation reaching the instance at ip through the bastion server
+    fn invocation(&self, instance_id: &str, ip: &str, remote_cmd: &str) -> String {
+        match self.topology {
+            Topology::Bastion => self.nested_invocation(instance_id, ip, remote_cmd),
+            Topology::ProxyCommand => self.proxied_invocation(instance_id, ip, remote_cmd),
+        }
+    }
+
+    /// Log in to the bastion and run the instance hop there. Only the bastion hop forwards your
+    /// agent, as the instance hop needs it to authenticate. The bastion records the instance's
+    /// host key under its instance id too, so a replacement reusing the ip is not refused.
+    fn nested_invocation(&self, instance_id: &str, ip: &str, remote_cmd: &str) -> String {
+        let hop = format!(
+            "ssh -o StrictHostKeyChecking={checking} -o HostKeyAlias={instance_id} -t {ec2_user}@{ip} {remote_cmd}",
+            checking = self.host_key_checking.ssh_option(),
+            instance_id = instance_id,
+            ec2_user = self.ec2_user,
+            ip = ip,
+            remote_cmd = remote_cmd,
+        );
+
+        format!(
+            "ssh{host_keys}{identities} -p {bastion_port} -A -t {bastion_user}@{dns_name} {hop}",
+            host_keys = self.host_key_options(),
+            identities = self.identity_options(&self.bastion_identity_files),
+            bastion_port = self.bastion_port,
+            bastion_user = self.bastion_user,
+            dns_name = self.dns_name,
+            hop = quote(&hop),
+        )
+    }
+
+    /// Run the bastion hop locally as a ProxyCommand, so that each hop authenticates with local
+    /// keys. The instance's host key is recorded under its instance id, as private ips are reused.
+    fn proxied_invocation(&self, instance_id: &str, ip: &str, remote_cmd: &str) -> String {
+        let proxy = format!(
+            "ssh{host_keys}{identities} -p {bastion_port} -W %h:%p {bastion_user}@{dns_name}",
+            host_keys = self.host_key_options(),
+            identities = self.identity_options(&self.bastion_identity_files),
+            bastion_port = self.bastion_port,
+            bastion_user = self.bastion_user,
+            dns_name = self.dns_name,
+        );
+
+        format!(
+            "ssh{host_keys} -o HostKeyAlias={instance_id}{identities} -o ProxyCommand={proxy} -t {ec2_user}@{ip} {remote_cmd}",
+            host_keys = self.host_key_options(),
+            instance_id = quote(instance_id),
+            identities = self.identity_options(&self.ec2_identity_files),
+            proxy = quote(&proxy),
+            ec2_user = self.ec2_user,
+            ip = ip,
+            remote_cmd = remote_cmd,
+        )
+    }
+
+    /// Forward a local port through the bastion to a port on the instance, without running a
+    /// command
+    pub fn tunnel(&self, ip: &str, local_port: u16, remote_port: u16) -> String {
+        format!(
+            "ssh{host_keys}{identities} -p {bastion_port} -N -L {local_port}:{ip}:{remote_port} {bastion_user}@{dns_name}",
+            host_keys = self.host_key_options(),
+            identities = self.identity_options(&self.bastion_identity_files),
+            bastion_port = self.bastion_port,
+            local_port = local_port,
+            ip = ip,
+            remote_port = remote_port,
+            bastion_user = self.bastion_user,
+            dns_name = self.dns_name,
+        )
+    }
+
+    fn host_key_options(&self) -> String {
+        format!(
+            " -o UserKnownHostsFile={} -o StrictHostKeyChecking={}",
+            quote(&self.known_hosts_file),
+            self.host_key_checking.ssh_option()
+        )
+    }
+
+    fn identity_options(&self, identity_files: &[String]) -> String {
+        let mut options: String = identity_files
+            .iter()
+            .map(|identity_file| format!(" -i {}", quote(identity_file)))
+            .collect();
+
+        if let Some(certificate_file) = &self.certificate_file {
+            options.push_str(&format!(" -o CertificateFile={}", quote(certificate_file)));
+        }
+
+        if self.identities_only {
+            options.push_str(" -o IdentitiesOnly=yes");
+        }
+
+        options
+    }
+}
+
 pub trait SshConnection: fmt::Display {
-    fn connection(
-        &self,
-        dns_name: String,
-        bastion_port: u16,
-        bastion_user: String,
-        ec2_user: String,
-        ssh_identity_file: String,
-        cmd: Vec<String>,
-    ) -> String;
+    fn connection(&self, settings: &SshSettings, cmd: Vec<String>) -> String;
+
+    /// The ec2 instance the connection lands on
+    fn instance_id(&self) -> &str;
+
+    /// The availability zone of the ec2 instance
+    fn availability_zone(&self) -> &str;
+
+    /// The private ip of the ec2 instance
+    fn private_ip(&self) -> &str;
+
+    /// The ecs task the connection lands in, if any
+    fn task_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// When the task started, or the instance launched
+    fn started_at(&self) -> Option<DateTime<Utc>>;
+
+    /// Details to list the connection with, one per column
+    fn columns(&self, now: DateTime<Utc>) -> Vec<String>;
+}
+
+/// A connection choice in a form which can be cached
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Choice {
+    Host(HostConnection),
+    Container(ContainerChoice),
+}
+
+impl Choice {
+    pub fn boxed(self) -> Box<dyn SshConnection> {
+        match self {
+            Choice::Host(host) => Box::new(host),
+            Choice::Container(container) => Box::new(container),
+        }
+    }
+}
+
+/// Describe each choice on a single line, with details aligned in columns
+pub fn aligned(choices: &[Box<dyn SshConnection>]) -> Vec<String> {
+    let now = Utc::now();
+    let rows: Vec<Vec<String>> = choices.iter().map(|choice| choice.columns(now)).collect();
+
+    let mut widths: Vec<usize> = Vec::new();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            let width = cell.chars().count();
+            match widths.get_mut(i) {
+                Some(max) => *max = (*max).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<String>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
+
+/// How long ago something happened, in its largest whole unit
+pub fn age(since: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let elapsed = now.signed_duration_since(since);
+
+    match elapsed {
+        e if e.num_minutes() < 1 => format!("{}s", e.num_seconds().max(0)),
+        e if e.num_hours() < 1 => format!("{}m", e.num_minutes()),
+        e if e.num_hours() < 48 => format!("{}h", e.num_hours()),
+        e => format!("{}d", e.num_days()),
+    }
 }
 
-#[derive(Debug)]
+fn started(started_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> String {
+    started_at
+        .map(|started_at| age(started_at, now))
+        .unwrap_or_else(|| "-".into())
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 // TODO(mmk) Do we really need to expose all of these as public fields?
 pub struct ContainerChoice {
     pub instance_id: String,
     pub instance_name: String,
     pub private_ip: String,
+    pub availability_zone: String,
+    pub task_id: String,
+    pub task_definition: Option<String>,
+    pub health_status: Option<String>,
+    pub started_at: Option<DateTime<Utc>>,
     pub name: String,
     pub runtime_id: String,
 }
@@ -198,33 +520,62 @@ impl fmt::Display for ContainerChoice {
 }
 
 impl SshConnection for ContainerChoice {
-    fn connection(
-        &self,
-        dns_name: String,
-        bastion_port: u16,
-        bastion_user: String,
-        ec2_user: String,
-        ssh_identity_file: String,
-        cmd: Vec<String>,
-    ) -> String {
-        format!(
-            "ssh -o StrictHostKeyChecking=no -i {identity_file} -p {bastion_port} -A -t {bastion_user}@{dns_name} \"ssh -o StrictHostKeyChecking=no -A -t {ec2_user}@{ip} \\\"docker exec -it --detach-keys 'ctrl-q,q' {docker_id} {cmd}\\\"\"",
-            identity_file=ssh_identity_file,
-            bastion_port=bastion_port,
-            bastion_user=bastion_user,
-            ec2_user=ec2_user,
-            dns_name=dns_name,
-            ip=self.private_ip,
-            docker_id=&self.runtime_id[..12],
-            cmd=cmd.join(" ")
+    fn connection(&self, settings: &SshSettings, cmd: Vec<String>) -> String {
+        settings.invocation(
+            &self.instance_id,
+            &self.private_ip,
+            &format!(
+                "\"docker exec -it --detach-keys 'ctrl-q,q' {docker_id} {cmd}\"",
+                docker_id = &self.runtime_id[..12],
+                cmd = cmd.join(" ")
+            ),
         )
     }
+
+    fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    fn availability_zone(&self) -> &str {
+        &self.availability_zone
+    }
+
+    fn private_ip(&self) -> &str {
+        &self.private_ip
+    }
+
+    fn task_id(&self) -> Option<&str> {
+        Some(&self.task_id)
+    }
+
+    fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.started_at
+    }
+
+    fn columns(&self, now: DateTime<Utc>) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.task_id.clone(),
+            self.task_definition.clone().unwrap_or_else(|| "-".into()),
+            self.health_status
+                .as_deref()
+                .unwrap_or("unknown")
+                .to_lowercase(),
+            started(self.started_at, now),
+            self.availability_zone.clone(),
+            self.private_ip.clone(),
+            format!("{} ({})", self.instance_name, self.instance_id),
+        ]
+    }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct HostConnection {
     pub name: String,
     pub private_ip: String,
     pub instance_id: String,
+    pub availability_zone: String,
+    pub launched_at: Option<DateTime<Utc>>,
 }
 
 impl fmt::Display for HostConnection {
@@ -238,24 +589,241 @@ impl fmt::Display for HostConnection {
 }
 
 impl SshConnection for HostConnection {
-    fn connection(
-        &self,
-        dns_name: String,
-        bastion_port: u16,
-        bastion_user: String,
-        ec2_user: String,
-        ssh_identity_file: String,
-        cmd: Vec<String>,
-    ) -> String {
-        format!(
-            "ssh -o StrictHostKeyChecking=no -i {identity_file} -p {bastion_port} -A -t {bastion_user}@{dns_name} ssh -o StrictHostKeyChecking=no -A -t {ec2_user}@{ip} {cmd}",
-            identity_file=ssh_identity_file,
-            bastion_port=bastion_port,
-            bastion_user=bastion_user,
-            ec2_user=ec2_user,
-            dns_name=dns_name,
-            ip=self.private_ip,
-            cmd=cmd.join(" ")
-        )
+    fn connection(&self, settings: &SshSettings, cmd: Vec<String>) -> String {
+        settings.invocation(&self.instance_id, &self.private_ip, &cmd.join(" "))
+    }
+
+    fn instance_id(&self) -> &str {
+        &self.instance_id
+    }
+
+    fn availability_zone(&self) -> &str {
+        &self.availability_zone
+    }
+
+    fn private_ip(&self) -> &str {
+        &self.private_ip
+    }
+
+    fn started_at(&self) -> Option<DateTime<Utc>> {
+        self.launched_at
+    }
+
+    fn columns(&self, now: DateTime<Utc>) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.instance_id.clone(),
+            started(self.launched_at, now),
+            self.availability_zone.clone(),
+            self.private_ip.clone(),
+        ]
+    }
+}
+
+/// Quote a value for use as a single shell word, leaving it untouched when it is already safe
+fn quote(value: &str) -> Cow<'_, str> {
+    let safe = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+
+    if safe {
+        Cow::Borrowed(value)
+    } else {
+        Cow::Owned(format!("'{}'", value.replace('\'', "'\\''")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        age, aligned, quote, ContainerChoice, HostConnection, HostKeyChecking, SshConnection,
+        SshSettings, Topology,
+    };
+    use chrono::{Duration, Utc};
+
+    fn settings() -> SshSettings {
+        SshSettings {
+            dns_name: "bastion.example.io".into(),
+            bastion_port: 1234,
+            bastion_user: "example-user".into(),
+            ec2_user: "ec2-user".into(),
+            bastion_identity_files: vec!["/home/example-user/.ssh/bastion".into()],
+            ec2_identity_files: vec![],
+            topology: Topology::ProxyCommand,
+            identities_only: false,
+            known_hosts_file: "/home/example-user/.local/share/heimdallr/known_hosts".into(),
+            host_key_checking: HostKeyChecking::AcceptNew,
+            instance_connect: false,
+            certificate: None,
+            certificate_file: None,
+        }
+    }
+
+    #[test]
+    fn each_hop_uses_its_own_identities() {
+        let host = HostConnection {
+            name: "StagingInstance1".into(),
+            private_ip: "10.0.0.1".into(),
+            instance_id: "i-12345678901234567".into(),
+            availability_zone: "us-east-1a".into(),
+            launched_at: None,
+        };
+
+        assert_eq!(
+            "ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=accept-new -o HostKeyAlias=i-12345678901234567 -o ProxyCommand='ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=accept-new -i /home/example-user/.ssh/bastion -p 1234 -W %h:%p example-user@bastion.example.io' -t ec2-user@10.0.0.1 bash",
+            host.connection(&settings(), vec!["bash".into()])
+        );
+
+        let settings = SshSettings {
+            ec2_identity_files: vec!["/keys/one".into(), "/keys/two".into()],
+            identities_only: true,
+            host_key_checking: HostKeyChecking::Console,
+            ..settings()
+        };
+
+        assert_eq!(
+            "ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=yes -o HostKeyAlias=i-12345678901234567 -i /keys/one -i /keys/two -o IdentitiesOnly=yes -o ProxyCommand='ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=yes -i /home/example-user/.ssh/bastion -o IdentitiesOnly=yes -p 1234 -W %h:%p example-user@bastion.example.io' -t ec2-user@10.0.0.1 ls -lah",
+            host.connection(&settings, vec!["ls".into(), "-lah".into()])
+        );
+
+        assert_eq!(
+            "ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=yes -i /home/example-user/.ssh/bastion -o IdentitiesOnly=yes -p 1234 -N -L 5432:10.0.0.1:5432 example-user@bastion.example.io",
+            settings.tunnel(host.private_ip(), 5432, 5432)
+        );
+    }
+
+    #[test]
+    fn the_bastion_topology_runs_the_instance_hop_on_the_bastion() {
+        let host = HostConnection {
+            name: "StagingInstance1".into(),
+            private_ip: "10.0.0.1".into(),
+            instance_id: "i-12345678901234567".into(),
+            availability_zone: "us-east-1a".into(),
+            launched_at: None,
+        };
+        let container = ContainerChoice {
+            instance_id: "i-12345678901234567".into(),
+            instance_name: "StagingInstance1".into(),
+            private_ip: "10.0.0.1".into(),
+            availability_zone: "us-east-1a".into(),
+            task_id: "abcd".into(),
+            task_definition: None,
+            health_status: None,
+            started_at: None,
+            name: "api".into(),
+            runtime_id: "0123456789abcdef".into(),
+        };
+        let settings = SshSettings {
+            topology: Topology::Bastion,
+            ..settings()
+        };
+
+        assert_eq!(
+            "ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=accept-new -i /home/example-user/.ssh/bastion -p 1234 -A -t example-user@bastion.example.io 'ssh -o StrictHostKeyChecking=accept-new -o HostKeyAlias=i-12345678901234567 -t ec2-user@10.0.0.1 bash'",
+            host.connection(&settings, vec!["bash".into()])
+        );
+        assert_eq!(
+            "ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=accept-new -i /home/example-user/.ssh/bastion -p 1234 -A -t example-user@bastion.example.io 'ssh -o StrictHostKeyChecking=accept-new -o HostKeyAlias=i-12345678901234567 -t ec2-user@10.0.0.1 \"docker exec -it --detach-keys '\\''ctrl-q,q'\\'' 0123456789ab bash\"'",
+            container.connection(&settings, vec!["bash".into()])
+        );
+
+        assert_eq!(None, settings.needs_proxy_command());
+        let shared = SshSettings {
+            ec2_identity_files: settings.bastion_identity_files.clone(),
+            ..settings.clone()
+        };
+        assert_eq!(None, shared.needs_proxy_command());
+        let instance_keys = SshSettings {
+            ec2_identity_files: vec!["/keys/instance".into()],
+            ..settings.clone()
+        };
+        assert_eq!(
+            Some("ec2_identity_file"),
+            instance_keys.needs_proxy_command()
+        );
+        let settings = SshSettings {
+            instance_connect: true,
+            ..settings
+        };
+        assert_eq!(Some("instance_connect"), settings.needs_proxy_command());
+    }
+
+    #[test]
+    fn session_keys_replace_identities_and_offer_their_certificate() {
+        let host = HostConnection {
+            name: "StagingInstance1".into(),
+            private_ip: "10.0.0.1".into(),
+            instance_id: "i-12345678901234567".into(),
+            availability_zone: "us-east-1a".into(),
+            launched_at: None,
+        };
+        let settings = SshSettings {
+            host_key_checking: HostKeyChecking::Off,
+            ..settings()
+        }
+        .with_session_key("/keys/ephemeral", Some("/keys/ephemeral-cert.pub"));
+
+        assert_eq!(
+            "ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=no -o HostKeyAlias=i-12345678901234567 -i /keys/ephemeral -o CertificateFile=/keys/ephemeral-cert.pub -o IdentitiesOnly=yes -o ProxyCommand='ssh -o UserKnownHostsFile=/home/example-user/.local/share/heimdallr/known_hosts -o StrictHostKeyChecking=no -i /keys/ephemeral -o CertificateFile=/keys/ephemeral-cert.pub -o IdentitiesOnly=yes -p 1234 -W %h:%p example-user@bastion.example.io' -t ec2-user@10.0.0.1 bash",
+            host.connection(&settings, vec!["bash".into()])
+        );
+    }
+
+    #[test]
+    fn choices_are_listed_in_aligned_columns() {
+        let started_at = Some(Utc::now() - Duration::minutes(5));
+        let choices: Vec<Box<dyn SshConnection>> = vec![
+            Box::new(ContainerChoice {
+                instance_id: "i-0123".into(),
+                instance_name: "StagingInstance1".into(),
+                private_ip: "10.0.0.1".into(),
+                availability_zone: "us-east-1a".into(),
+                task_id: "abcd".into(),
+                task_definition: Some("api:12".into()),
+                health_status: Some("HEALTHY".into()),
+                started_at,
+                name: "api".into(),
+                runtime_id: "0123456789abcdef".into(),
+            }),
+            Box::new(ContainerChoice {
+                instance_id: "i-4567".into(),
+                instance_name: "StagingInstance2".into(),
+                private_ip: "10.0.10.2".into(),
+                availability_zone: "us-east-1b".into(),
+                task_id: "ef01".into(),
+                task_definition: Some("api:9".into()),
+                health_status: None,
+                started_at: None,
+                name: "api".into(),
+                runtime_id: "0123456789abcdef".into(),
+            }),
+        ];
+
+        assert_eq!(
+            vec![
+                "api  abcd  api:12  healthy  5m  us-east-1a  10.0.0.1   StagingInstance1 (i-0123)",
+                "api  ef01  api:9   unknown  -   us-east-1b  10.0.10.2  StagingInstance2 (i-4567)",
+            ],
+            aligned(&choices)
+        );
+    }
+
+    #[test]
+    fn ages_use_their_largest_unit() {
+        let now = Utc::now();
+
+        assert_eq!("42s", age(now - Duration::seconds(42), now));
+        assert_eq!("59m", age(now - Duration::minutes(59), now));
+        assert_eq!("47h", age(now - Duration::hours(47), now));
+        assert_eq!("3d", age(now - Duration::days(3), now));
+    }
+
+    #[test]
+    fn quote_only_wraps_unsafe_values() {
+        assert_eq!("/home/user/.ssh/id_rsa", quote("/home/user/.ssh/id_rsa"));
+        assert_eq!("'/home/a user/id_rsa'", quote("/home/a user/id_rsa"));
+        assert_eq!("'it'\\''s'", quote("it's"));
+        assert_eq!("''", quote(""));
     }
 }
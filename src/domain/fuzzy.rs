@@ -0,0 +1,66 @@
+/// Similarity below which a name is not worth suggesting
+const THRESHOLD: f64 = 0.75;
+
+/// Most suggestions to offer
+const LIMIT: usize = 10;
+
+/// Names close to the query, most similar first. Names containing the query rank above any
+/// others, so partial names find what they are part of.
+pub fn near_matches(query: &str, names: &[String]) -> Vec<String> {
+    let query = query.to_lowercase();
+
+    let mut scored: Vec<(f64, &String)> = names
+        .iter()
+        .map(|name| (score(&query, &name.to_lowercase()), name))
+        .filter(|(score, _)| *score >= THRESHOLD)
+        .collect();
+
+    scored.sort_by(|(a_score, a_name), (b_score, b_name)| {
+        b_score
+            .partial_cmp(a_score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then_with(|| a_name.cmp(b_name))
+    });
+    scored.dedup_by(|(_, a), (_, b)| a == b);
+
+    scored
+        .into_iter()
+        .take(LIMIT)
+        .map(|(_, name)| name.clone())
+        .collect()
+}
+
+fn score(query: &str, name: &str) -> f64 {
+    let similarity = strsim::jaro_winkler(query, name);
+
+    if !query.is_empty() && name.contains(query) {
+        1.0 + similarity
+    } else {
+        similarity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::near_matches;
+
+    #[test]
+    fn typos_and_partial_names_are_suggested() {
+        let names: Vec<String> = vec![
+            "StagingInstance1".into(),
+            "StagingInstance2".into(),
+            "ProductionApi".into(),
+            "bastion".into(),
+        ];
+
+        assert_eq!(
+            vec!["StagingInstance1".to_string(), "StagingInstance2".into()],
+            near_matches("StagingInstnce1", &names)
+        );
+        assert_eq!(
+            vec!["ProductionApi".to_string()],
+            near_matches("production", &names)
+        );
+        assert!(near_matches("database", &names).is_empty());
+    }
+}
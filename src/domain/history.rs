@@ -0,0 +1,122 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How many connections are remembered, dropping the oldest first
+const LIMIT: usize = 500;
+
+/// A connection made with connect
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    pub profile: String,
+    /// The target as it was given, before aliases were expanded
+    pub target: String,
+    pub instance_id: String,
+    pub task_id: Option<String>,
+    pub connected_at: DateTime<Utc>,
+}
+
+/// Past connections, one json entry per line, oldest first
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The history in the user's data directory
+    pub fn managed() -> Option<Self> {
+        dirs::data_dir().map(|dir| Self::new(dir.join("heimdallr").join("history")))
+    }
+
+    pub fn record(&self, entry: Entry) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        entries.push(entry);
+        let skip = entries.len().saturating_sub(LIMIT);
+
+        let contents: String = entries[skip..]
+            .iter()
+            .filter_map(|entry| serde_json::to_string(entry).ok())
+            .map(|line| format!("{}\n", line))
+            .collect();
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Renamed into place, so a session interrupted mid-write, or two recording at once, cannot
+        // truncate the history
+        let written = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&written, contents)?;
+        fs::rename(&written, &self.path).inspect_err(|_| {
+            let _ = fs::remove_file(&written);
+        })
+    }
+
+    /// Every recorded connection, oldest first. Lines which can not be read are skipped.
+    pub fn entries(&self) -> io::Result<Vec<Entry>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(contents
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The targets most recently connected to with the profile, newest first, each listed once
+    pub fn recent(&self, profile: &str) -> io::Result<Vec<Entry>> {
+        let mut recent: Vec<Entry> = Vec::new();
+
+        for entry in self.entries()?.into_iter().rev() {
+            if entry.profile == profile && !recent.iter().any(|seen| seen.target == entry.target) {
+                recent.push(entry);
+            }
+        }
+
+        Ok(recent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entry, History};
+    use chrono::Utc;
+    use std::fs;
+
+    #[test]
+    fn recent_targets_are_listed_newest_first_once_per_profile() {
+        let path = std::env::temp_dir().join(format!("heimdallr-history-{}", std::process::id()));
+        let history = History::new(&path);
+        let entry = |profile: &str, target: &str| Entry {
+            profile: profile.into(),
+            target: target.into(),
+            instance_id: "i-0123".into(),
+            task_id: None,
+            connected_at: Utc::now(),
+        };
+
+        history.record(entry("staging", "api")).unwrap();
+        history.record(entry("staging", "cluster#api")).unwrap();
+        history.record(entry("production", "api")).unwrap();
+        history.record(entry("staging", "api")).unwrap();
+
+        let targets: Vec<String> = history
+            .recent("staging")
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.target)
+            .collect();
+        assert_eq!(vec!["api", "cluster#api"], targets);
+
+        fs::remove_file(&path).unwrap();
+        assert!(history.recent("staging").unwrap().is_empty());
+    }
+}
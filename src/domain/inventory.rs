@@ -0,0 +1,39 @@
+use super::connections::HostConnection;
+use chrono::{DateTime, Utc};
+
+/// A running ec2 instance
+#[derive(Clone, Debug)]
+pub struct Instance {
+    /// The Env tag instances are grouped by
+    pub environment: Option<String>,
+    pub name: String,
+    pub instance_id: String,
+    pub private_ip: Option<String>,
+    pub availability_zone: String,
+    pub launched_at: Option<DateTime<Utc>>,
+}
+
+impl Instance {
+    /// How to connect to the instance, if it can be reached at all
+    pub fn connection(&self) -> Option<HostConnection> {
+        Some(HostConnection {
+            name: self.name.clone(),
+            private_ip: self.private_ip.clone()?,
+            instance_id: self.instance_id.clone(),
+            availability_zone: self.availability_zone.clone(),
+            launched_at: self.launched_at,
+        })
+    }
+}
+
+/// An ecs service
+#[derive(Clone, Debug)]
+pub struct Service {
+    pub cluster: String,
+    pub name: String,
+    pub status: String,
+    pub running_count: i64,
+    pub desired_count: i64,
+    /// The task definition family and revision, e.g. api:12
+    pub task_definition: Option<String>,
+}
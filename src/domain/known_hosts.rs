@@ -0,0 +1,187 @@
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CONSOLE_KEYS_BEGIN: &str = "-----BEGIN SSH HOST KEY KEYS-----";
+const CONSOLE_KEYS_END: &str = "-----END SSH HOST KEY KEYS-----";
+
+/// How ssh treats host keys it has not seen before
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyChecking {
+    /// Record the key presented on the first connection and refuse it if it later changes
+    #[default]
+    AcceptNew,
+    /// Record the keys each instance printed to its console on boot and refuse any other key
+    Console,
+    /// Refuse any key that is not already recorded
+    Strict,
+    /// Accept any key without checking it
+    Off,
+}
+
+impl HostKeyChecking {
+    /// The matching value of ssh's StrictHostKeyChecking option
+    pub fn ssh_option(self) -> &'static str {
+        match self {
+            HostKeyChecking::AcceptNew => "accept-new",
+            HostKeyChecking::Console | HostKeyChecking::Strict => "yes",
+            HostKeyChecking::Off => "no",
+        }
+    }
+}
+
+impl fmt::Display for HostKeyChecking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyChecking::AcceptNew => write!(f, "accept_new"),
+            HostKeyChecking::Console => write!(f, "console"),
+            HostKeyChecking::Strict => write!(f, "strict"),
+            HostKeyChecking::Off => write!(f, "off"),
+        }
+    }
+}
+
+/// A known_hosts file managed by heimdallr, kept apart from ~/.ssh/known_hosts
+pub struct KnownHosts {
+    path: PathBuf,
+}
+
+impl KnownHosts {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The known hosts file in the user's data directory
+    pub fn managed() -> Option<Self> {
+        dirs::data_dir().map(|dir| Self::new(dir.join("heimdallr").join("known_hosts")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Replace every key recorded for the host with the given keys
+    pub fn replace(&self, host: &str, keys: &[String]) -> io::Result<()> {
+        let existing = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let mut contents: String = existing
+            .lines()
+            .filter(|line| !matches_host(line, host))
+            .map(|line| format!("{}\n", line))
+            .collect();
+
+        for key in keys {
+            contents.push_str(&format!("{} {}\n", host, key));
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&self.path, contents)
+    }
+}
+
+/// The name ssh looks up in known_hosts for a host on the given port
+pub fn host_pattern(host: &str, port: u16) -> String {
+    match port {
+        22 => host.into(),
+        port => format!("[{}]:{}", host, port),
+    }
+}
+
+/// Host keys an instance printed to its console on boot, as `type base64` pairs
+pub fn console_host_keys(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.contains(CONSOLE_KEYS_BEGIN))
+        .skip(1)
+        .take_while(|line| !line.contains(CONSOLE_KEYS_END))
+        .filter_map(|line| {
+            // Some images prefix each console line, e.g. with "ec2: "
+            let mut fields = line
+                .split_whitespace()
+                .skip_while(|field| !is_key_type(field));
+
+            match (fields.next(), fields.next()) {
+                (Some(key_type), Some(key)) => Some(format!("{} {}", key_type, key)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn is_key_type(field: &str) -> bool {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-") || field.starts_with("sk-")
+}
+
+fn matches_host(line: &str, host: &str) -> bool {
+    line.split_whitespace()
+        .next()
+        .map(|hosts| hosts.split(',').any(|pattern| pattern == host))
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{console_host_keys, host_pattern, KnownHosts};
+    use std::fs;
+
+    #[test]
+    fn console_host_keys_are_read_between_markers() {
+        let output = "\
+ec2: #############################################################
+ec2: -----BEGIN SSH HOST KEY FINGERPRINTS-----
+ec2: 256 SHA256:47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU no comment (ED25519)
+ec2: -----END SSH HOST KEY FINGERPRINTS-----
+ec2: #############################################################
+-----BEGIN SSH HOST KEY KEYS-----
+ecdsa-sha2-nistp256 AAAAE2VjZHNh root@ip-10-0-0-1
+ssh-ed25519 AAAAC3NzaC1lZDI1NTE5 root@ip-10-0-0-1
+-----END SSH HOST KEY KEYS-----
+";
+
+        assert_eq!(
+            vec![
+                "ecdsa-sha2-nistp256 AAAAE2VjZHNh".to_string(),
+                "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5".to_string()
+            ],
+            console_host_keys(output)
+        );
+        assert!(console_host_keys("no keys were printed").is_empty());
+    }
+
+    #[test]
+    fn replace_only_rewrites_the_given_host() {
+        let path =
+            std::env::temp_dir().join(format!("heimdallr-known-hosts-{}", std::process::id()));
+        fs::write(
+            &path,
+            "i-0123 ssh-ed25519 OLD\nbastion.example.io ssh-ed25519 BASTION\n",
+        )
+        .unwrap();
+
+        KnownHosts::new(&path)
+            .replace("i-0123", &["ssh-ed25519 NEW".into()])
+            .unwrap();
+
+        assert_eq!(
+            "bastion.example.io ssh-ed25519 BASTION\ni-0123 ssh-ed25519 NEW\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!("bastion.example.io", host_pattern("bastion.example.io", 22));
+        assert_eq!(
+            "[bastion.example.io]:1234",
+            host_pattern("bastion.example.io", 1234)
+        );
+    }
+}
@@ -1 +1,8 @@
+pub mod cache;
 pub mod connections;
+pub mod fuzzy;
+pub mod history;
+pub mod inventory;
+pub mod known_hosts;
+pub mod selection;
+pub mod target;
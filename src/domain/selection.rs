@@ -0,0 +1,212 @@
+use super::connections::SshConnection;
+use super::target::Target;
+use rand::Rng;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+#[error("Invalid pick {0}. Expected first, newest, oldest, random or index:N.")]
+pub struct Error(String);
+
+/// Why a pick could not choose between the matching targets
+#[derive(Debug, Error, PartialEq)]
+pub enum Unpickable {
+    #[error("Cannot pick index:{index}, as only {count} targets match")]
+    OutOfRange { index: usize, count: usize },
+
+    #[error("Cannot pick by start time, as none of the {0} matching targets has one")]
+    NoStartTimes(usize),
+}
+
+/// How to choose between several matching targets without asking
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pick {
+    First,
+    Newest,
+    Oldest,
+    Random,
+    /// The nth candidate, counting from 1, in the order candidates are listed
+    Index(usize),
+}
+
+impl FromStr for Pick {
+    type Err = Error;
+
+    fn from_str(pick: &str) -> Result<Self, Self::Err> {
+        match pick {
+            "first" => Ok(Pick::First),
+            "newest" => Ok(Pick::Newest),
+            "oldest" => Ok(Pick::Oldest),
+            "random" => Ok(Pick::Random),
+            _ => match pick.strip_prefix("index:").map(str::parse) {
+                Some(Ok(index)) if index > 0 => Ok(Pick::Index(index)),
+                _ => Err(Error(pick.into())),
+            },
+        }
+    }
+}
+
+/// A target along with the selectors narrowing down what it matches
+pub struct Selection {
+    pub target: Target,
+    pub instance_id: Option<String>,
+    pub task_id: Option<String>,
+    pub pick: Option<Pick>,
+}
+
+impl Selection {
+    pub fn new(target: Target) -> Self {
+        Self {
+            target,
+            instance_id: None,
+            task_id: None,
+            pick: None,
+        }
+    }
+
+    /// Narrow the choices down with the selectors. Choices are sorted so that candidates are
+    /// always listed, and picked by index, in the same order. More than one choice is left when
+    /// nothing picks between them.
+    pub fn apply(
+        &self,
+        choices: Vec<Box<dyn SshConnection>>,
+    ) -> Result<Vec<Box<dyn SshConnection>>, Unpickable> {
+        let mut choices: Vec<Box<dyn SshConnection>> = choices
+            .into_iter()
+            .filter(|choice| match &self.instance_id {
+                Some(instance_id) => choice.instance_id() == instance_id,
+                None => true,
+            })
+            .filter(|choice| match &self.task_id {
+                Some(task_id) => choice.task_id() == Some(task_id.as_str()),
+                None => true,
+            })
+            .collect();
+        choices.sort_by_key(|choice| choice.to_string());
+
+        if choices.len() < 2 {
+            return Ok(choices);
+        }
+
+        let started = || (0..choices.len()).filter(|&i| choices[i].started_at().is_some());
+
+        let index = match self.pick {
+            None => return Ok(choices),
+            Some(Pick::First) => Some(0),
+            Some(Pick::Newest) => started().max_by_key(|&i| choices[i].started_at()),
+            Some(Pick::Oldest) => started().min_by_key(|&i| choices[i].started_at()),
+            Some(Pick::Random) => Some(rand::thread_rng().gen_range(0..choices.len())),
+            Some(Pick::Index(index)) if index > choices.len() => {
+                return Err(Unpickable::OutOfRange {
+                    index,
+                    count: choices.len(),
+                })
+            }
+            Some(Pick::Index(index)) => Some(index - 1),
+        };
+
+        match index {
+            Some(index) => Ok(vec![choices.swap_remove(index)]),
+            None => Err(Unpickable::NoStartTimes(choices.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Pick, Selection, Unpickable};
+    use crate::domain::connections::{HostConnection, SshConnection};
+    use crate::domain::target::Target;
+    use chrono::{TimeZone, Utc};
+
+    fn choices() -> Vec<Box<dyn SshConnection>> {
+        vec![("b", 2), ("a", 1), ("c", 3)]
+            .into_iter()
+            .map(|(name, day)| {
+                Box::new(HostConnection {
+                    name: name.into(),
+                    private_ip: "10.0.0.1".into(),
+                    instance_id: format!("i-{}", name),
+                    availability_zone: "us-east-1a".into(),
+                    launched_at: Utc
+                        .ymd_opt(2021, 5, day)
+                        .single()
+                        .map(|d| d.and_hms(0, 0, 0)),
+                }) as Box<dyn SshConnection>
+            })
+            .collect()
+    }
+
+    fn picked(selection: Selection) -> Vec<String> {
+        selection
+            .apply(choices())
+            .unwrap()
+            .iter()
+            .map(|choice| choice.instance_id().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn picks_parse() {
+        assert_eq!(Ok(Pick::Newest), "newest".parse());
+        assert_eq!(Ok(Pick::Index(2)), "index:2".parse());
+        assert_eq!(Err(Error("index:0".into())), "index:0".parse::<Pick>());
+        assert_eq!(Err(Error("last".into())), "last".parse::<Pick>());
+    }
+
+    #[test]
+    fn selectors_narrow_down_choices() {
+        let selection = |pick| Selection {
+            pick,
+            ..Selection::new(Target::Name("*".into()))
+        };
+
+        assert_eq!(vec!["i-a", "i-b", "i-c"], picked(selection(None)));
+        assert_eq!(vec!["i-a"], picked(selection(Some(Pick::First))));
+        assert_eq!(vec!["i-c"], picked(selection(Some(Pick::Newest))));
+        assert_eq!(vec!["i-a"], picked(selection(Some(Pick::Oldest))));
+        assert_eq!(vec!["i-b"], picked(selection(Some(Pick::Index(2)))));
+
+        let by_instance = Selection {
+            instance_id: Some("i-b".into()),
+            ..Selection::new(Target::Name("*".into()))
+        };
+        assert_eq!(vec!["i-b"], picked(by_instance));
+    }
+
+    #[test]
+    fn picks_which_cannot_choose_are_errors() {
+        let selection = |pick| Selection {
+            pick: Some(pick),
+            ..Selection::new(Target::Name("*".into()))
+        };
+
+        assert_eq!(
+            Some(Unpickable::OutOfRange { index: 4, count: 3 }),
+            selection(Pick::Index(4)).apply(choices()).err()
+        );
+
+        let unstarted = || {
+            vec!["a", "b"]
+                .into_iter()
+                .map(|name| {
+                    Box::new(HostConnection {
+                        name: name.into(),
+                        private_ip: "10.0.0.1".into(),
+                        instance_id: format!("i-{}", name),
+                        availability_zone: "us-east-1a".into(),
+                        launched_at: None,
+                    }) as Box<dyn SshConnection>
+                })
+                .collect()
+        };
+        assert_eq!(
+            Some(Unpickable::NoStartTimes(2)),
+            selection(Pick::Oldest).apply(unstarted()).err()
+        );
+        assert_eq!(
+            Some(Unpickable::NoStartTimes(2)),
+            selection(Pick::Newest).apply(unstarted()).err()
+        );
+    }
+}
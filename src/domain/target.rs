@@ -0,0 +1,365 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Tag holding the environment of an instance, for env/name targets
+pub const ENVIRONMENT_TAG: &str = "Env";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum Error {
+    #[error("No target specified.")]
+    Empty,
+
+    #[error("Invalid target {0}. Expected cluster#service or cluster#service#container.")]
+    Service(String),
+
+    #[error("Invalid target {0}. Expected tag:Key=Value.")]
+    Tag(String),
+
+    #[error("Invalid target {0}. Expected environment/name.")]
+    Environment(String),
+
+    #[error("Invalid bastion {0}. Expected bastion_user@host, bastion_user@ or host.")]
+    Bastion(String),
+}
+
+/// What to connect to, as given on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// An ec2 instance id, e.g. i-0123456789abcdef0
+    InstanceId(String),
+    /// The private ip address of an instance
+    PrivateIp(Ipv4Addr),
+    /// Instances with a tag, given as tag:Key=Value
+    Tag { key: String, value: String },
+    /// Instances with a Name tag in an environment, given as environment/name
+    Environment { environment: String, name: String },
+    /// Containers of an ecs service, given as cluster#service or cluster#service#container
+    Service {
+        cluster: String,
+        service: String,
+        container: Option<String>,
+    },
+    /// Instances with a Name tag, which may contain the * and ? wildcards understood by EC2
+    /// filters
+    Name(String),
+}
+
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        if target.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        if target.contains('#') {
+            let parts: Vec<&str> = target.split('#').collect();
+            return match parts[..] {
+                [cluster, service] if !cluster.is_empty() && !service.is_empty() => {
+                    Ok(Target::Service {
+                        cluster: cluster.into(),
+                        service: service.into(),
+                        container: None,
+                    })
+                }
+                [cluster, service, container]
+                    if !cluster.is_empty() && !service.is_empty() && !container.is_empty() =>
+                {
+                    Ok(Target::Service {
+                        cluster: cluster.into(),
+                        service: service.into(),
+                        container: Some(container.into()),
+                    })
+                }
+                _ => Err(Error::Service(target.into())),
+            };
+        }
+
+        if let Some(tag) = target.strip_prefix("tag:") {
+            return match tag.split_once('=') {
+                Some((key, value)) if !key.is_empty() && !value.is_empty() => Ok(Target::Tag {
+                    key: key.into(),
+                    value: value.into(),
+                }),
+                _ => Err(Error::Tag(target.into())),
+            };
+        }
+
+        if is_instance_id(target) {
+            return Ok(Target::InstanceId(target.into()));
+        }
+
+        if let Ok(ip) = target.parse::<Ipv4Addr>() {
+            return Ok(Target::PrivateIp(ip));
+        }
+
+        if let Some((environment, name)) = target.split_once('/') {
+            return match (environment, name) {
+                (environment, name)
+                    if !environment.is_empty() && !name.is_empty() && !name.contains('/') =>
+                {
+                    Ok(Target::Environment {
+                        environment: environment.into(),
+                        name: name.into(),
+                    })
+                }
+                _ => Err(Error::Environment(target.into())),
+            };
+        }
+
+        Ok(Target::Name(target.into()))
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::InstanceId(id) => write!(f, "{}", id),
+            Target::PrivateIp(ip) => write!(f, "{}", ip),
+            Target::Tag { key, value } => write!(f, "tag:{}={}", key, value),
+            Target::Environment { environment, name } => write!(f, "{}/{}", environment, name),
+            Target::Service {
+                cluster,
+                service,
+                container: None,
+            } => write!(f, "{}#{}", cluster, service),
+            Target::Service {
+                cluster,
+                service,
+                container: Some(container),
+            } => write!(f, "{}#{}#{}", cluster, service, container),
+            Target::Name(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A target along with the user to reach it as, written as user@target
+#[derive(Clone, Debug, PartialEq)]
+pub struct Destination {
+    pub user: Option<String>,
+    pub target: Target,
+}
+
+impl FromStr for Destination {
+    type Err = Error;
+
+    fn from_str(destination: &str) -> Result<Self, Self::Err> {
+        let (user, target) = match destination.split_once('@') {
+            Some((user, target)) if is_user(user) => (Some(user.into()), target),
+            _ => (None, destination),
+        };
+
+        Ok(Destination {
+            user,
+            target: target.parse()?,
+        })
+    }
+}
+
+/// The bastion to connect through, given with --via as bastion_user@host, bastion_user@ to keep
+/// the profile's host, or host to keep the profile's user
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bastion {
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl FromStr for Bastion {
+    type Err = Error;
+
+    fn from_str(bastion: &str) -> Result<Self, Self::Err> {
+        let (user, host) = match bastion.split_once('@') {
+            Some((user, _)) if !user.is_empty() && !is_user(user) => {
+                return Err(Error::Bastion(bastion.into()))
+            }
+            Some((user, host)) => (non_empty(user), non_empty(host)),
+            None => (None, non_empty(bastion)),
+        };
+
+        if user.is_none() && host.is_none() {
+            return Err(Error::Bastion(bastion.into()));
+        }
+
+        Ok(Bastion { user, host })
+    }
+}
+
+/// Whether the value looks like a unix user name, rather than part of a tag query
+fn is_user(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    match value {
+        "" => None,
+        value => Some(value.into()),
+    }
+}
+
+/// Instance ids are i- followed by 8 or 17 hex digits
+fn is_instance_id(value: &str) -> bool {
+    match value.strip_prefix("i-") {
+        Some(id) => (id.len() == 8 || id.len() == 17) && id.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bastion, Destination, Error, Target};
+
+    fn parse(target: &str) -> Result<Target, Error> {
+        target.parse()
+    }
+
+    #[test]
+    fn parses_every_target_form() {
+        assert_eq!(
+            Ok(Target::InstanceId("i-0123456789abcdef0".into())),
+            parse("i-0123456789abcdef0")
+        );
+        assert_eq!(
+            Ok(Target::InstanceId("i-1234abcd".into())),
+            parse("i-1234abcd")
+        );
+        assert_eq!(
+            Ok(Target::PrivateIp("10.0.1.23".parse().unwrap())),
+            parse("10.0.1.23")
+        );
+        assert_eq!(
+            Ok(Target::Tag {
+                key: "Role".into(),
+                value: "api".into()
+            }),
+            parse("tag:Role=api")
+        );
+        assert_eq!(
+            Ok(Target::Environment {
+                environment: "staging".into(),
+                name: "api".into()
+            }),
+            parse("staging/api")
+        );
+        assert_eq!(
+            Ok(Target::Service {
+                cluster: "cluster".into(),
+                service: "service".into(),
+                container: None
+            }),
+            parse("cluster#service")
+        );
+        assert_eq!(
+            Ok(Target::Service {
+                cluster: "cluster".into(),
+                service: "service".into(),
+                container: Some("container".into())
+            }),
+            parse("cluster#service#container")
+        );
+        assert_eq!(
+            Ok(Target::Name("StagingInstance1".into())),
+            parse("StagingInstance1")
+        );
+    }
+
+    #[test]
+    fn near_misses_fall_back_to_names() {
+        assert_eq!(Ok(Target::Name("i-api".into())), parse("i-api"));
+        assert_eq!(Ok(Target::Name("10.0.1".into())), parse("10.0.1"));
+    }
+
+    #[test]
+    fn malformed_targets_are_rejected() {
+        assert_eq!(Err(Error::Empty), parse(""));
+        assert_eq!(Err(Error::Service("cluster#".into())), parse("cluster#"));
+        assert_eq!(Err(Error::Service("a#b#c#d".into())), parse("a#b#c#d"));
+        assert_eq!(Err(Error::Tag("tag:Role".into())), parse("tag:Role"));
+        assert_eq!(
+            Err(Error::Environment("staging/".into())),
+            parse("staging/")
+        );
+        assert_eq!(Err(Error::Environment("a/b/c".into())), parse("a/b/c"));
+    }
+
+    #[test]
+    fn destinations_override_the_user() {
+        let destination = |value: &str| value.parse::<Destination>();
+
+        assert_eq!(
+            Ok(Destination {
+                user: Some("ubuntu".into()),
+                target: Target::Name("api".into()),
+            }),
+            destination("ubuntu@api")
+        );
+        assert_eq!(
+            Ok(Destination {
+                user: Some("ubuntu".into()),
+                target: Target::Environment {
+                    environment: "staging".into(),
+                    name: "api".into()
+                },
+            }),
+            destination("ubuntu@staging/api")
+        );
+        assert_eq!(
+            Ok(Target::Tag {
+                key: "Owner".into(),
+                value: "me@example.io".into()
+            }),
+            destination("tag:Owner=me@example.io").map(|d| d.target)
+        );
+    }
+
+    #[test]
+    fn bastions_take_a_user_a_host_or_both() {
+        let bastion = |value: &str| value.parse::<Bastion>();
+
+        assert_eq!(
+            Ok(Bastion {
+                user: Some("admin".into()),
+                host: Some("bastion.example.io".into()),
+            }),
+            bastion("admin@bastion.example.io")
+        );
+        assert_eq!(
+            Ok(Bastion {
+                user: Some("admin".into()),
+                host: None,
+            }),
+            bastion("admin@")
+        );
+        assert_eq!(
+            Ok(Bastion {
+                user: None,
+                host: Some("bastion.example.io".into()),
+            }),
+            bastion("bastion.example.io")
+        );
+        assert_eq!(Err(Error::Bastion("@".into())), bastion("@"));
+        assert_eq!(
+            Err(Error::Bastion("a b@bastion".into())),
+            bastion("a b@bastion")
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for target in &[
+            "i-0123456789abcdef0",
+            "10.0.1.23",
+            "tag:Role=api",
+            "staging/api",
+            "cluster#service",
+            "cluster#service#container",
+            "StagingInstance1",
+        ] {
+            assert_eq!(*target, parse(target).unwrap().to_string());
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use tokio::process::Command;
+use tracing::warn;
+
+pub mod signer;
+
+/// Keys left behind by printed commands are removed by later connections once this old
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// A throwaway ed25519 keypair, generated for every connection at a path of its own so that
+/// concurrent connections do not replace each other's key. The key and its certificate are removed
+/// once it is dropped, unless it is kept for a command run after heimdallr exits.
+pub struct EphemeralKey {
+    pub private_key: PathBuf,
+    pub public_key: String,
+    kept: bool,
+}
+
+impl EphemeralKey {
+    /// A key which would be written to a fresh path, without generating it
+    pub fn planned() -> Result<Self> {
+        Ok(Self {
+            private_key: Self::unique_path()?,
+            public_key: String::new(),
+            kept: true,
+        })
+    }
+
+    /// Generate a new keypair with ssh-keygen, removing stale keys left by earlier connections
+    pub async fn generate() -> Result<Self> {
+        let dir = Self::dir()?;
+        fs::create_dir_all(&dir)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        remove_stale(&dir);
+
+        let private_key = Self::unique_path()?;
+        let public_file = private_key.with_extension("pub");
+
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "heimdallr"])
+            .arg("-f")
+            .arg(&private_key)
+            .status()
+            .await
+            .context("Unable to run ssh-keygen")?;
+
+        // Removes whatever ssh-keygen wrote should anything below fail
+        let mut key = Self {
+            private_key,
+            public_key: String::new(),
+            kept: false,
+        };
+
+        if !status.success() {
+            return Err(anyhow!("ssh-keygen exited with {}", status));
+        }
+
+        key.public_key = fs::read_to_string(&public_file)
+            .with_context(|| format!("Unable to read {}", public_file.display()))?
+            .trim()
+            .to_string();
+
+        Ok(key)
+    }
+
+    /// Leave the key in place once dropped, for a printed command to use
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+
+    fn dir() -> Result<PathBuf> {
+        dirs::cache_dir()
+            .map(|dir| dir.join("heimdallr").join("keys"))
+            .ok_or_else(|| anyhow!("Unable to determine cache directory."))
+    }
+
+    fn unique_path() -> Result<PathBuf> {
+        Ok(Self::dir()?.join(format!(
+            "ephemeral-{}-{:08x}",
+            process::id(),
+            rand::random::<u32>()
+        )))
+    }
+
+    fn files(&self) -> [PathBuf; 3] {
+        [
+            self.private_key.clone(),
+            self.private_key.with_extension("pub"),
+            self.certificate_path(),
+        ]
+    }
+
+    /// Where ssh expects the certificate for this key
+    pub fn certificate_path(&self) -> PathBuf {
+        Self::certificate_path_for(&self.private_key)
+    }
+
+    fn certificate_path_for(private_key: &Path) -> PathBuf {
+        let mut name = private_key.as_os_str().to_owned();
+        name.push("-cert.pub");
+        PathBuf::from(name)
+    }
+}
+
+impl Drop for EphemeralKey {
+    fn drop(&mut self) {
+        if self.kept {
+            return;
+        }
+
+        for file in &self.files() {
+            match fs::remove_file(file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    warn!("Unable to remove {}: {}", file.display(), e)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Remove the files of keys generated more than STALE_AFTER ago
+fn remove_stale(dir: &Path) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .map(|age| age > STALE_AFTER)
+            .unwrap_or(false);
+
+        if stale && entry.file_name().to_string_lossy().starts_with("ephemeral") {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
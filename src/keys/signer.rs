@@ -0,0 +1,139 @@
+use super::EphemeralKey;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Validity of a certificate when none is configured, in ssh-keygen's -V format. Starting a
+/// minute early allows for clock skew between this machine and the servers.
+pub const DEFAULT_VALIDITY: &str = "-1m:+5m";
+
+/// Who signs ephemeral keys
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Signer {
+    /// Sign with a CA private key on disk. Meant for testing, as the CA key should not normally
+    /// be on your machine.
+    Local { ca_key: String },
+    /// Run a command which reads the public key on stdin and prints the certificate on stdout.
+    /// The requested principals and validity are passed as HEIMDALLR_PRINCIPALS and
+    /// HEIMDALLR_VALIDITY.
+    Command { command: String },
+}
+
+impl fmt::Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Signer::Local { ca_key } => write!(f, "local CA {}", ca_key),
+            Signer::Command { command } => write!(f, "command `{}`", command),
+        }
+    }
+}
+
+/// How certificates are requested for a profile
+#[derive(Clone, Debug, PartialEq)]
+pub struct Certificate {
+    pub signer: Signer,
+    /// Validity interval in ssh-keygen's -V format
+    pub validity: String,
+    pub principals: Vec<String>,
+}
+
+impl Certificate {
+    /// Have the key signed, returning the path of its certificate
+    pub async fn sign(&self, key: &EphemeralKey) -> Result<PathBuf> {
+        let certificate_file = key.certificate_path();
+
+        match &self.signer {
+            Signer::Local { ca_key } => {
+                let status = Command::new("ssh-keygen")
+                    .arg("-q")
+                    .arg("-s")
+                    .arg(ca_key)
+                    .args(["-I", "heimdallr"])
+                    .arg("-n")
+                    .arg(self.principals.join(","))
+                    .arg("-V")
+                    .arg(&self.validity)
+                    .arg(key.private_key.with_extension("pub"))
+                    .status()
+                    .await
+                    .context("Unable to run ssh-keygen")?;
+
+                if !status.success() {
+                    return Err(anyhow!("ssh-keygen exited with {}", status));
+                }
+            }
+            Signer::Command { command } => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("HEIMDALLR_PRINCIPALS", self.principals.join(","))
+                    .env("HEIMDALLR_VALIDITY", &self.validity)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("Unable to run `{}`", command))?;
+
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin
+                        .write_all(format!("{}\n", key.public_key).as_bytes())
+                        .await?;
+                }
+
+                let output = child.wait_with_output().await?;
+                if !output.status.success() {
+                    return Err(anyhow!("`{}` exited with {}", command, output.status));
+                }
+
+                let certificate = String::from_utf8_lossy(&output.stdout);
+                if certificate.trim().is_empty() {
+                    return Err(anyhow!("`{}` did not print a certificate", command));
+                }
+
+                fs::write(&certificate_file, format!("{}\n", certificate.trim()))?;
+            }
+        }
+
+        Ok(certificate_file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Certificate, Signer};
+    use crate::keys::EphemeralKey;
+    use std::fs;
+
+    #[tokio::test]
+    async fn command_signer_writes_its_output_as_the_certificate() {
+        let dir = std::env::temp_dir().join(format!("heimdallr-signer-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let key = EphemeralKey {
+            private_key: dir.join("ephemeral"),
+            public_key: "ssh-ed25519 AAAA heimdallr".into(),
+            kept: false,
+        };
+        let certificate = Certificate {
+            signer: Signer::Command {
+                command: "printf '%s %s' \"$(cut -d' ' -f2)\" \"$HEIMDALLR_PRINCIPALS\"".into(),
+            },
+            validity: "+5m".into(),
+            principals: vec!["bastion-user".into(), "ec2-user".into()],
+        };
+
+        let path = certificate.sign(&key).await.unwrap();
+
+        assert_eq!(dir.join("ephemeral-cert.pub"), path);
+        assert_eq!(
+            "AAAA bastion-user,ec2-user\n",
+            fs::read_to_string(&path).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
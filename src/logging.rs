@@ -0,0 +1,28 @@
+use std::io::{self, IsTerminal};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding a log filter, e.g. heimdallr=debug, taking precedence over -v
+const FILTER_VARIABLE: &str = "HEIMDALLR_LOG";
+
+/// Log to stderr, so stdout only ever holds the output of commands. Each -v shows more: lookups and
+/// their timing, then every AWS request, then everything the libraries we use log too.
+pub fn init(verbosity: u64) {
+    let default = match verbosity {
+        0 => "warn",
+        1 => "heimdallr=info",
+        2 => "heimdallr=debug",
+        _ => "trace",
+    };
+
+    let filter =
+        EnvFilter::try_from_env(FILTER_VARIABLE).unwrap_or_else(|_| EnvFilter::new(default));
+
+    // Closing a span logs how long it was open, which times each lookup
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .try_init();
+}
@@ -1,23 +1,55 @@
-use anyhow::{anyhow, Result};
-use rusoto_core::{region, HttpClient};
-use rusoto_credential::ProfileProvider;
-use rusoto_ec2::Ec2Client;
-use rusoto_ecs::EcsClient;
+use anyhow::Result;
+use domain::cache::Cache;
+use domain::connections::SshSettings;
+use domain::history::History;
+use domain::selection::{Pick, Selection};
+use domain::target::{Bastion, Destination};
+use std::io;
+use std::path::PathBuf;
+use structopt::clap::{AppSettings, Shell};
 use structopt::StructOpt;
 
 mod application;
+mod credentials;
 mod domain;
+mod keys;
+mod logging;
 mod settings;
 mod ui;
 
 /// Connect to AWS EC2 hosts via a Bastion / Jump host
 #[derive(StructOpt)]
-#[structopt(name = "heimdallr", global_settings = &[structopt::clap::AppSettings::AllowLeadingHyphen])]
+#[structopt(name = "heimdallr", global_settings = &[AppSettings::AllowLeadingHyphen])]
 struct Heimdallr {
     /// Profile name as specified in your configuration file
-    #[structopt(name = "profile", long, short = "p", default_value = "default")]
+    #[structopt(
+        name = "profile",
+        long,
+        short = "p",
+        env = "HEIMDALLR_PROFILE",
+        default_value = "default"
+    )]
     profile: String,
 
+    /// Additional configuration file, taking precedence over ~/.config/heimdallr.toml and
+    /// ./.heimdallr.toml
+    #[structopt(name = "config", long, short = "c", env = "HEIMDALLR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Print the changes and commands that would be run without applying or executing them
+    #[structopt(name = "dry-run", long)]
+    dry_run: bool,
+
+    /// Look instances and tasks up in AWS again instead of using the cached inventory
+    #[structopt(name = "refresh", long)]
+    refresh: bool,
+
+    /// Log what is looked up in AWS, and how long it took, to stderr. Repeat for more detail: -vv
+    /// logs each request, filter and retry. HEIMDALLR_LOG takes precedence, e.g.
+    /// HEIMDALLR_LOG=heimdallr=debug
+    #[structopt(name = "verbose", long, short = "v", parse(from_occurrences))]
+    verbose: u64,
+
     #[structopt(subcommand)]
     cmd: Command,
 }
@@ -48,122 +80,395 @@ enum Command {
     },
 
     /// Connect to a running instance
-    Connect {
-        /// Override the host name of the bastion server for the specified profile
-        #[structopt(name = "dns-name", long, short = "d")]
-        dns_name: Option<String>,
-
-        /// Override the ssh port of the bastion server for the specified profile
-        #[structopt(name = "bastion-port", long, short = "p")]
-        bastion_port: Option<u16>,
-
-        /// Override the ssh user of the bastion server for the specified profile
-        #[structopt(name = "bastion-user", long, short = "u")]
-        bastion_user: Option<String>,
-
-        /// Override the user of the ec2 server for the specified profile
-        #[structopt(name = "ec2-user", long, short = "e")]
-        ec2_user: Option<String>,
-
-        // TODO(mmk) Is there a better variable type to verify that the file exists?
-        /// Override the ssh identity file to use for the specified profile
-        #[structopt(name = "identity-file", long, short = "i")]
-        identity_file: Option<String>,
-
-        /// The target to connect. Supported formats are host, user@host, cluster#service,
-        /// cluster#service#container
-        #[structopt()]
-        target: String,
+    Connect(Box<ConnectCommand>),
 
-        /// An optional command to execute on the specified target
-        #[structopt(default_value = "bash")]
-        cmd: Vec<String>,
+    /// List the targets recently connected to with the profile, or connect to one again
+    Recent {
+        /// Connect to the nth most recent target, counting from 1
+        #[structopt()]
+        index: Option<usize>,
     },
 
+    /// Browse instances and services in a full screen terminal ui
+    Ui,
+
+    /// Create, inspect and validate profiles
+    Config(ConfigCommand),
+
     /// Update this executable to the latest version
     Update,
+
+    /// Print a completion script for your shell, completing profiles and connect targets too
+    Completions {
+        #[structopt(possible_values = &["bash", "zsh", "fish"])]
+        shell: Shell,
+    },
+}
+
+#[derive(Default, StructOpt)]
+struct ConnectCommand {
+    /// Override the host name of the bastion server for the specified profile
+    #[structopt(name = "dns-name", long, short = "d")]
+    dns_name: Option<String>,
+
+    /// Connect through this bastion, given as bastion_user@host, bastion_user@ or host
+    #[structopt(name = "via", long, conflicts_with_all = &["dns-name", "bastion-user"])]
+    via: Option<Bastion>,
+
+    /// Override the ssh port of the bastion server for the specified profile
+    #[structopt(name = "bastion-port", long, short = "p")]
+    bastion_port: Option<u16>,
+
+    /// Override the ssh user of the bastion server for the specified profile
+    #[structopt(name = "bastion-user", long, short = "u")]
+    bastion_user: Option<String>,
+
+    /// Override the user of the ec2 server for the specified profile
+    #[structopt(name = "ec2-user", long, short = "e")]
+    ec2_user: Option<String>,
+
+    /// Override the ssh identity files used for both hops for the specified profile. May be
+    /// given more than once
+    #[structopt(name = "identity-file", long, short = "i", number_of_values = 1)]
+    identity_file: Vec<String>,
+
+    /// Override the ssh identity files used for the bastion server. May be given more than
+    /// once
+    #[structopt(name = "bastion-identity-file", long, number_of_values = 1)]
+    bastion_identity_file: Vec<String>,
+
+    /// Override the ssh identity files used for the ec2 server. May be given more than once
+    #[structopt(name = "ec2-identity-file", long, number_of_values = 1)]
+    ec2_identity_file: Vec<String>,
+
+    /// Only offer the given identity files, even if ssh-agent holds other keys
+    #[structopt(name = "identities-only", long)]
+    identities_only: bool,
+
+    /// Push an ephemeral key to the bastion and instance with EC2 Instance Connect and
+    /// authenticate with it
+    #[structopt(name = "instance-connect", long)]
+    instance_connect: bool,
+
+    /// Run the session, adding your IP to the bastion security group for its duration and
+    /// revoking it on exit
+    #[structopt(name = "grant", long, short = "g")]
+    grant: bool,
+
+    /// Choose between several matching targets without asking: first, newest, oldest,
+    /// random or index:N, counting from 1
+    #[structopt(name = "pick", long)]
+    pick: Option<Pick>,
+
+    /// Only consider targets on this ec2 instance
+    #[structopt(name = "instance-id", long)]
+    instance_id: Option<String>,
+
+    /// Only consider containers of this ecs task
+    #[structopt(name = "task-id", long)]
+    task_id: Option<String>,
+
+    /// The target to connect. Supported formats are name, an instance id, a private ip,
+    /// tag:Key=Value, environment/name, cluster#service and cluster#service#container.
+    /// Prefix with user@ to override the ec2 user. An alias from your configuration stands for
+    /// its target, and - for the last target connected to with the profile
+    #[structopt()]
+    target: String,
+
+    /// An optional command to execute on the specified target
+    #[structopt(default_value = "bash")]
+    cmd: Vec<String>,
+}
+
+/// Print the candidates for a word being completed, used by the completion scripts. Parsed on its
+/// own, as clap cannot generate completions for a command named __complete.
+#[derive(StructOpt)]
+#[structopt(name = "heimdallr __complete")]
+struct Complete {
+    #[structopt(
+        name = "profile",
+        long,
+        short = "p",
+        env = "HEIMDALLR_PROFILE",
+        default_value = "default"
+    )]
+    profile: String,
+
+    #[structopt(name = "config", long, short = "c", env = "HEIMDALLR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Wait for lookups however long they take, instead of finishing them in the background
+    #[structopt(long)]
+    wait: bool,
+
+    #[structopt(possible_values = &["profiles", "targets"])]
+    kind: String,
+
+    #[structopt(default_value = "", allow_hyphen_values = true)]
+    word: String,
+}
+
+#[derive(StructOpt)]
+enum ConfigCommand {
+    /// Interactively create a new profile
+    Init,
+
+    /// Show the effective settings of a profile and where each one is set
+    Show {
+        /// The profile to show. Defaults to the global profile option
+        #[structopt()]
+        profile: Option<String>,
+    },
+
+    /// Check that every profile is complete
+    Validate,
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let settings = settings::Settings::new()?;
+async fn main() {
+    if let Err(e) = run().await {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+/// The documented exit code for an error, falling back to 1
+fn exit_code(error: &anyhow::Error) -> i32 {
+    use application::{error::Error, security_groups};
+
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<Error>() {
+            return e.exit_code();
+        }
+
+        // Not being allowed to change the bastion security group is an authorization failure
+        if let Some(security_groups::Error::PermissionDenied(group)) = cause.downcast_ref() {
+            return Error::Auth(group.clone()).exit_code();
+        }
+    }
+
+    1
+}
+
+async fn run() -> Result<()> {
+    if std::env::args().nth(1).as_deref() == Some("__complete") {
+        let opt = Complete::from_iter(std::env::args().skip(1));
+        let settings = settings::Settings::new(opt.config.as_deref())?;
+        return ui::completions::complete(&settings, &opt.profile, &opt.kind, &opt.word, opt.wait)
+            .await;
+    }
+
     let opt = Heimdallr::from_args();
+    // Anything logged would be drawn over the full screen ui
+    if !matches!(opt.cmd, Command::Ui) {
+        logging::init(opt.verbose);
+    }
+
+    if let Command::Completions { shell } = opt.cmd {
+        return ui::completions::script(&mut Heimdallr::clap(), shell, &mut io::stdout());
+    }
 
-    let profile_settings = match settings.profiles.get(&opt.profile) {
-        Some(s) => Ok(s),
-        _ => Err(anyhow!(
-            "Could not find specified profile entry {}. Please check your configuration file.",
-            &opt.profile
-        )),
-    }?;
+    let settings = settings::Settings::new(opt.config.as_deref())?;
 
-    let mut provider = ProfileProvider::new()?;
-    provider.set_profile(profile_settings.aws_profile.clone());
+    // These commands must work before a valid profile exists
+    let cmd = match opt.cmd {
+        Command::Config(ConfigCommand::Init) => {
+            return ui::config::init(&settings, opt.config).await
+        }
+        Command::Config(ConfigCommand::Show { profile }) => {
+            return ui::config::show(&settings, &profile.unwrap_or(opt.profile))
+        }
+        Command::Config(ConfigCommand::Validate) => return ui::config::validate(&settings),
+        Command::Update => return update(opt.dry_run).await,
+        Command::Ui => return ui::tui::run(&settings, &opt.profile, opt.dry_run).await,
+        Command::Recent { index: None } => {
+            return ui::history::recent(&ui::history::managed()?, &opt.profile)
+        }
+        Command::Recent { index: Some(index) } => Command::Connect(Box::new(ConnectCommand {
+            target: ui::history::target(&ui::history::managed()?, &opt.profile, index)?,
+            cmd: vec!["bash".into()],
+            ..Default::default()
+        })),
+        cmd => cmd,
+    };
 
-    let region = profile_settings.aws_region.parse::<region::Region>()?;
+    let profile_settings = &settings.profile(&opt.profile)?;
 
-    let ec2_client = Ec2Client::new_with(HttpClient::new()?, provider.clone(), region.clone());
-    let ecs_client = EcsClient::new_with(HttpClient::new()?, provider, region);
+    let clients = application::Clients::new(&opt.profile, profile_settings)?;
+    let cache = Cache::managed(
+        &opt.profile,
+        &profile_settings.aws_region,
+        profile_settings.cache_ttl,
+        opt.refresh,
+    );
 
-    let security_group_handler = application::security_groups::Handler::new(&ec2_client);
-    let list_instances_handler = application::list_instances::Handler::new(&ec2_client);
-    let connect_handler = application::connect::Handler::new(&ecs_client, &ec2_client);
+    let security_group_handler =
+        application::security_groups::Handler::new(&clients.ec2, opt.dry_run);
+    let list_instances_handler = application::list_instances::Handler::new(&clients.ec2, &cache);
+    let connect_handler = application::connect::Handler::new(
+        &clients.ecs,
+        &clients.ec2,
+        &clients.instance_connect,
+        &cache,
+    );
 
-    match opt.cmd {
-        Command::List => ui::list::list(list_instances_handler).await,
+    match cmd {
+        Command::List => {
+            ui::timed("Listed instances", ui::list::list(list_instances_handler)).await
+        }
         Command::Grant {
             security_group_id,
             description,
         } => {
-            security_group_handler
-                .grant_access(
-                    security_group_id.unwrap_or_else(|| profile_settings.security_group_id.clone()),
-                    description,
-                )
-                .await
+            ui::security_groups::grant(
+                security_group_handler,
+                security_group_id.unwrap_or_else(|| profile_settings.security_group_id.clone()),
+                description,
+            )
+            .await
         }
         Command::Revoke { security_group_id } => {
-            security_group_handler
-                .revoke_access(
-                    security_group_id.unwrap_or_else(|| profile_settings.security_group_id.clone()),
-                )
-                .await
-        }
-        Command::Connect {
-            dns_name,
-            bastion_port,
-            bastion_user,
-            ec2_user,
-            identity_file,
-            target,
-            cmd,
-        } => {
-            ui::connect::connect(
-                connect_handler,
-                dns_name.unwrap_or_else(|| profile_settings.dns_name.clone()),
-                bastion_port.unwrap_or_else(|| profile_settings.bastion_port),
-                bastion_user.unwrap_or_else(|| profile_settings.bastion_user.clone()),
-                ec2_user.unwrap_or_else(|| profile_settings.ec2_user.clone()),
-                identity_file.unwrap_or_else(|| profile_settings.identity_file.clone()),
-                &target,
-                cmd,
+            ui::security_groups::revoke(
+                security_group_handler,
+                security_group_id.unwrap_or_else(|| profile_settings.security_group_id.clone()),
             )
             .await
         }
-        Command::Update => {
-            tokio::task::spawn_blocking(move || {
-                let status = self_update::backends::github::Update::configure()
-                    .repo_owner("keelerm84")
-                    .repo_name(env!("CARGO_PKG_NAME"))
-                    .bin_name("heimdallr")
-                    .show_download_progress(true)
-                    .current_version(env!("CARGO_PKG_VERSION"))
-                    .build()?
-                    .update()?;
-                println!("Update status: `{}`!", status.version());
+        Command::Connect(connect) => {
+            let ConnectCommand {
+                dns_name,
+                via,
+                bastion_port,
+                bastion_user,
+                ec2_user,
+                identity_file,
+                bastion_identity_file,
+                ec2_identity_file,
+                identities_only,
+                instance_connect,
+                grant,
+                pick,
+                instance_id,
+                task_id,
+                target,
+                cmd,
+            } = *connect;
+
+            let given = match target.as_str() {
+                "-" => ui::history::target(&ui::history::managed()?, &opt.profile, 1)?,
+                _ => target,
+            };
+            let target: Destination = settings.alias(&given).unwrap_or(&given).parse()?;
+
+            let identities = |hop: Vec<String>, profile: Vec<String>| -> Result<Vec<String>> {
+                let files = match (hop.is_empty(), identity_file.is_empty()) {
+                    (false, _) => hop,
+                    (true, false) => identity_file.clone(),
+                    (true, true) => return Ok(profile),
+                };
+
+                Ok(files
+                    .iter()
+                    .map(|file| settings::expand_path(file))
+                    .collect::<Result<_, _>>()?)
+            };
+
+            let defaults = profile_settings.ssh_settings()?;
+            let ssh_settings = SshSettings {
+                dns_name: via
+                    .as_ref()
+                    .and_then(|via| via.host.clone())
+                    .or(dns_name)
+                    .unwrap_or(defaults.dns_name),
+                bastion_port: bastion_port.unwrap_or(defaults.bastion_port),
+                bastion_user: via
+                    .and_then(|via| via.user)
+                    .or(bastion_user)
+                    .unwrap_or(defaults.bastion_user),
+                ec2_user: target.user.or(ec2_user).unwrap_or(defaults.ec2_user),
+                bastion_identity_files: identities(
+                    bastion_identity_file,
+                    defaults.bastion_identity_files,
+                )?,
+                ec2_identity_files: identities(ec2_identity_file, defaults.ec2_identity_files)?,
+                identities_only: identities_only || defaults.identities_only,
+                instance_connect: instance_connect || defaults.instance_connect,
+                ..defaults
+            };
+
+            ui::connect::check_topology(&ssh_settings)?;
+            for file in ssh_settings.identity_files() {
+                settings::check_identity_file(file)?;
+            }
+
+            let selection = Selection {
+                instance_id,
+                task_id,
+                pick,
+                ..Selection::new(target.target)
+            };
+
+            let choice = ui::connect::choose(&connect_handler, &selection).await?;
+
+            let prepared = if opt.dry_run {
+                ui::connect::planned(&ssh_settings)?
+            } else {
+                let prepared =
+                    ui::connect::prepare(&connect_handler, &ssh_settings, choice.as_ref()).await?;
+                // Without a data directory there is nowhere to remember connections, which only
+                // recent and connect - need
+                if let Some(history) = History::managed() {
+                    ui::history::record(&history, &opt.profile, &given, choice.as_ref());
+                }
+                prepared
+            };
+
+            if grant || profile_settings.auto_grant {
+                ui::connect::connect_with_grant(
+                    security_group_handler,
+                    profile_settings.security_group_id.clone(),
+                    prepared,
+                    choice.as_ref(),
+                    cmd,
+                    opt.dry_run,
+                )
+                .await
+            } else {
+                ui::connect::connect(prepared, choice.as_ref(), cmd);
                 Ok(())
-            })
-            .await?
+            }
         }
+        Command::Config(_)
+        | Command::Update
+        | Command::Ui
+        | Command::Recent { .. }
+        | Command::Completions { .. } => unreachable!("handled before loading the profile"),
     }
 }
+
+async fn update(dry_run: bool) -> Result<()> {
+    tokio::task::spawn_blocking(move || {
+        let updater = self_update::backends::github::Update::configure()
+            .repo_owner("keelerm84")
+            .repo_name(env!("CARGO_PKG_NAME"))
+            .bin_name("heimdallr")
+            .show_download_progress(true)
+            .current_version(env!("CARGO_PKG_VERSION"))
+            .build()?;
+
+        if dry_run {
+            let latest = updater.get_latest_release()?;
+            println!(
+                "Would update from `{}` to `{}`",
+                updater.current_version(),
+                latest.version
+            );
+            return Ok(());
+        }
+
+        let status = updater.update()?;
+        println!("Update status: `{}`!", status.version());
+        Ok(())
+    })
+    .await?
+}
@@ -1,32 +1,444 @@
-use config::{Config, ConfigError, File};
+use config::{Config, ConfigError, Environment, File};
 use dirs::config_dir;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::{env, fmt, fs};
+use thiserror::Error;
 
-#[derive(Debug, Deserialize)]
+use crate::domain::connections::{SshSettings, Topology};
+use crate::domain::known_hosts::{HostKeyChecking, KnownHosts};
+use crate::keys::signer::{self, Certificate, Signer};
+
+/// Name of the per project configuration file, looked up in the current directory
+const PROJECT_FILE: &str = ".heimdallr.toml";
+
+/// Prefix of the environment variables settings are read from
+const ENVIRONMENT_PREFIX: &str = "HEIMDALLR_CFG";
+
+/// How many seconds cached inventory is used for, unless a profile says otherwise
+const DEFAULT_CACHE_TTL: u64 = 300;
+
+/// How many seconds a single AWS request may take, unless a profile says otherwise
+const DEFAULT_REQUEST_TIMEOUT: u64 = 10;
+
+/// How many times an AWS request is attempted before giving up, unless a profile says otherwise
+const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Unable to read configuration from {source_name}: {error}")]
+    Read {
+        source_name: String,
+        error: ConfigError,
+    },
+
+    #[error("Could not find specified profile entry {name}. Please check your configuration file. Available profiles: {available}")]
+    MissingProfile { name: String, available: String },
+
+    #[error("Profile {profile} is missing the required setting `{key}`. Set it in [profiles.{profile}] or [defaults].")]
+    MissingKey { profile: String, key: &'static str },
+
+    #[error("Profile {profile} extends {parent}, which does not exist")]
+    MissingParent { profile: String, parent: String },
+
+    #[error(
+        "{0} sets `extends` under [defaults], where it has no effect. Set it in a profile instead."
+    )]
+    ExtendsInDefaults(String),
+
+    #[error("{source_name} sets `{key}`, which runs a command. Anyone can put a .heimdallr.toml in a directory, so commands are only run from your global configuration or a file passed with --config.")]
+    UntrustedCommand { source_name: String, key: String },
+
+    #[error("Profile inheritance forms a cycle: {0}")]
+    CyclicInheritance(String),
+
+    #[error("Unable to expand {value}: environment variable {variable} is not set")]
+    UndefinedVariable { value: String, variable: String },
+
+    #[error("Identity file {0} does not exist")]
+    MissingIdentityFile(String),
+
+    #[error("Identity file {path} is accessible by other users (mode {mode:o}). Run `chmod 600 {path}` to fix.")]
+    InsecureIdentityFile { path: String, mode: u32 },
+}
+
+/// The settings found in a single configuration source
+#[derive(Debug, Default, Deserialize)]
+pub struct Layer {
+    /// Where these settings were read from
+    #[serde(skip)]
+    pub source: String,
+
+    /// Settings applied to every profile unless the profile overrides them
+    #[serde(default)]
+    pub defaults: PartialProfile,
+
+    #[serde(default)]
+    pub profiles: HashMap<String, PartialProfile>,
+
+    /// Names standing in for connect targets, e.g. api = "prod-cluster#api#web"
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+#[derive(Debug)]
 pub struct Settings {
-    pub profiles: HashMap<String, Profile>,
+    /// Configuration sources, from lowest to highest precedence
+    pub layers: Vec<Layer>,
 }
 
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
-        if let Some(dir) = config_dir() {
-            let mut dir = dir;
-            dir.push("heimdallr.toml");
+    /// Load settings from, in increasing order of precedence, the global configuration file, a
+    /// .heimdallr.toml in the current directory, the file passed with --config, and
+    /// HEIMDALLR_CFG_* environment variables.
+    pub fn new(config_file: Option<&Path>) -> Result<Self, Error> {
+        let mut layers = Vec::new();
+
+        if let Some(path) = global_config_file() {
+            layers.push(Layer::from_file(&path, false)?);
+        }
+
+        layers.push(Layer::from_project_file(&PathBuf::from(PROJECT_FILE))?);
+
+        if let Some(path) = config_file {
+            layers.push(Layer::from_file(path, true)?);
+        }
+
+        layers.push(Layer::from_environment()?);
 
-            let mut s = Config::default();
-            s.merge(File::from(dir))?;
+        Ok(Self { layers })
+    }
+
+    /// Names of every profile defined in any configuration source
+    pub fn profile_names(&self) -> Vec<String> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.profiles.keys().cloned())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// Names of every alias defined in any configuration source
+    pub fn alias_names(&self) -> Vec<String> {
+        self.layers
+            .iter()
+            .flat_map(|layer| layer.aliases.keys().cloned())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .collect()
+    }
+
+    /// The target an alias stands for. Aliases are matched ignoring case, as configuration keys
+    /// are lower cased when they are read.
+    pub fn alias(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.aliases.get(&name))
+            .map(String::as_str)
+    }
+
+    /// Resolve the named profile by merging, source by source, the defaults and then the profile
+    /// and the profiles it extends. A later source's defaults win over an earlier source's
+    /// profile.
+    pub fn profile(&self, name: &str) -> Result<Profile, Error> {
+        let ancestry = self.ancestry(name)?;
+        let mut profile = PartialProfile::default();
+
+        for layer in &self.layers {
+            profile.merge(&layer.defaults);
+
+            for ancestor in ancestry.iter().rev() {
+                if let Some(overrides) = layer.profiles.get(ancestor) {
+                    profile.merge(overrides);
+                }
+            }
+        }
+
+        profile.validate(name)
+    }
 
-            return s.try_into();
+    /// Resolve the named profile like `profile`, reporting which source each setting came from
+    /// instead of failing on missing settings
+    pub fn explain(&self, name: &str) -> Result<Vec<Origin>, Error> {
+        // Optional settings start out with the value they default to
+        let defaults = PartialProfile {
+            identities_only: Some(false),
+            topology: Some(Topology::default()),
+            host_key_checking: Some(HostKeyChecking::default()),
+            instance_connect: Some(false),
+            certificate_validity: Some(signer::DEFAULT_VALIDITY.into()),
+            auto_grant: Some(false),
+            cache_ttl: Some(DEFAULT_CACHE_TTL),
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+            max_attempts: Some(DEFAULT_MAX_ATTEMPTS),
+            credentials: Some(Credentials::default()),
+            ..Default::default()
+        };
+
+        let mut origins: Vec<Origin> = defaults
+            .entries()
+            .into_iter()
+            .map(|(key, value)| Origin {
+                key,
+                value,
+                source: None,
+            })
+            .collect();
+
+        let mut apply = |profile: &PartialProfile, source: String| {
+            for (origin, (_, value)) in origins.iter_mut().zip(profile.entries()) {
+                if value.is_some() {
+                    origin.value = value;
+                    origin.source = Some(source.clone());
+                }
+            }
+        };
+
+        let ancestry = self.ancestry(name)?;
+
+        for layer in &self.layers {
+            apply(&layer.defaults, format!("{} [defaults]", layer.source));
+
+            for ancestor in ancestry.iter().rev() {
+                if let Some(overrides) = layer.profiles.get(ancestor) {
+                    apply(
+                        overrides,
+                        format!("{} [profiles.{}]", layer.source, ancestor),
+                    );
+                }
+            }
         }
 
-        Err(ConfigError::Message(String::from(
-            "Unable to determine configuration directory.",
-        )))
+        Ok(origins)
+    }
+
+    /// Check for settings which are accepted in [defaults] but cannot apply there
+    pub fn check_defaults(&self) -> Vec<Error> {
+        self.layers
+            .iter()
+            .filter(|layer| layer.defaults.extends.is_some())
+            .map(|layer| Error::ExtendsInDefaults(layer.source.clone()))
+            .collect()
     }
+
+    /// The named profile followed by each profile it extends, nearest first
+    fn ancestry(&self, name: &str) -> Result<Vec<String>, Error> {
+        if !self.has_profile(name) {
+            return Err(Error::MissingProfile {
+                name: name.into(),
+                available: self.profile_names().join(", "),
+            });
+        }
+
+        let mut ancestry = vec![name.to_string()];
+
+        while let Some(parent) = self.extends(ancestry.last().unwrap()) {
+            if ancestry.contains(&parent) {
+                ancestry.push(parent);
+                return Err(Error::CyclicInheritance(ancestry.join(" -> ")));
+            }
+
+            if !self.has_profile(&parent) {
+                return Err(Error::MissingParent {
+                    profile: ancestry.last().unwrap().clone(),
+                    parent,
+                });
+            }
+
+            ancestry.push(parent);
+        }
+
+        Ok(ancestry)
+    }
+
+    fn has_profile(&self, name: &str) -> bool {
+        self.layers
+            .iter()
+            .any(|layer| layer.profiles.contains_key(name))
+    }
+
+    /// The profile extended by the named profile, as set by the highest precedence source
+    fn extends(&self, name: &str) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .filter_map(|layer| layer.profiles.get(name))
+            .find_map(|profile| profile.extends.clone())
+    }
+}
+
+/// The value of a single setting, and the configuration source that provided it
+pub struct Origin {
+    pub key: &'static str,
+    pub value: Option<String>,
+    pub source: Option<String>,
+}
+
+/// Location of the global configuration file
+pub fn global_config_file() -> Option<PathBuf> {
+    let mut path = config_dir()?;
+    path.push("heimdallr.toml");
+
+    Some(path)
 }
 
-#[derive(Debug, Deserialize)]
+/// Expand a leading ~ and any $VAR or ${VAR} references in a path
+pub fn expand_path(path: &str) -> Result<String, Error> {
+    expand_path_with(path, |name| env::var(name).ok())
+}
+
+/// Expand a path like `expand_path`, looking variables up with `lookup`. An unterminated `${` is
+/// left as it is.
+fn expand_path_with(path: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let undefined = |variable: &str| Error::UndefinedVariable {
+        value: path.into(),
+        variable: variable.into(),
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path;
+
+    if rest == "~" || rest.starts_with("~/") {
+        let home = dirs::home_dir().ok_or_else(|| undefined("HOME"))?;
+        expanded.push_str(&home.display().to_string());
+        rest = &rest[1..];
+    }
+
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            expanded.push(c);
+            continue;
+        }
+
+        let mut name = String::new();
+        if chars.peek() == Some(&'{') {
+            chars.next();
+
+            let mut terminated = false;
+            for c in chars.by_ref() {
+                if c == '}' {
+                    terminated = true;
+                    break;
+                }
+                name.push(c);
+            }
+
+            if !terminated {
+                expanded.push_str("${");
+                expanded.push_str(&name);
+                continue;
+            }
+        } else {
+            while let Some(c) = chars
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || **c == '_')
+            {
+                name.push(*c);
+                chars.next();
+            }
+        }
+
+        if name.is_empty() {
+            expanded.push('$');
+            continue;
+        }
+
+        expanded.push_str(&lookup(&name).ok_or_else(|| undefined(&name))?);
+    }
+
+    Ok(expanded)
+}
+
+/// Ensure an identity file exists and is private, as ssh refuses to use keys others can read
+pub fn check_identity_file(path: &str) -> Result<(), Error> {
+    let metadata = fs::metadata(path).map_err(|_| Error::MissingIdentityFile(path.into()))?;
+    let mode = metadata.permissions().mode() & 0o777;
+
+    if mode & 0o077 != 0 {
+        return Err(Error::InsecureIdentityFile {
+            path: path.into(),
+            mode,
+        });
+    }
+
+    Ok(())
+}
+
+impl Layer {
+    fn from_file(path: &Path, required: bool) -> Result<Self, Error> {
+        let source = path.display().to_string();
+
+        let mut config = Config::default();
+        config
+            .merge(File::from(path).required(required))
+            .map_err(|error| Error::Read {
+                source_name: source.clone(),
+                error,
+            })?;
+
+        Self::from_config(config, source)
+    }
+
+    /// Read a project file, refusing settings which run a command. Running heimdallr in a checkout
+    /// must not run whatever the checkout asks for.
+    fn from_project_file(path: &Path) -> Result<Self, Error> {
+        let layer = Self::from_file(path, false)?;
+
+        let sections = std::iter::once((String::from("defaults"), &layer.defaults)).chain(
+            layer
+                .profiles
+                .iter()
+                .map(|(name, profile)| (format!("profiles.{}", name), profile)),
+        );
+
+        for (section, profile) in sections {
+            if let Some(key) = profile.command_key() {
+                return Err(Error::UntrustedCommand {
+                    source_name: layer.source.clone(),
+                    key: format!("{}.{}", section, key),
+                });
+            }
+        }
+
+        Ok(layer)
+    }
+
+    /// Read settings such as HEIMDALLR_CFG_PROFILES__PRODUCTION__DNS_NAME or
+    /// HEIMDALLR_CFG_DEFAULTS__EC2_USER from the environment. The prefix keeps variables like
+    /// HEIMDALLR_PROFILE and HEIMDALLR_LOG from being read as settings.
+    fn from_environment() -> Result<Self, Error> {
+        let source = String::from("environment");
+
+        let mut config = Config::default();
+        config
+            .merge(Environment::with_prefix(ENVIRONMENT_PREFIX).separator("__"))
+            .map_err(|error| Error::Read {
+                source_name: source.clone(),
+                error,
+            })?;
+
+        Self::from_config(config, source)
+    }
+
+    fn from_config(config: Config, source: String) -> Result<Self, Error> {
+        let mut layer: Layer = config.try_into().map_err(|error| Error::Read {
+            source_name: source.clone(),
+            error,
+        })?;
+        layer.source = source;
+
+        Ok(layer)
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct Profile {
     /// Profile name as specified in your ~/.aws/credentials file
     pub aws_profile: String,
@@ -49,6 +461,744 @@ pub struct Profile {
     /// The user of the ec2 server
     pub ec2_user: String,
 
-    /// The ssh identity file to use
-    pub identity_file: String,
+    /// The ssh identity files offered to the bastion server. When empty, ssh falls back to its
+    /// own configuration and ssh-agent.
+    pub bastion_identity_files: Vec<String>,
+
+    /// The ssh identity files offered to the ec2 server. When empty, ssh falls back to its own
+    /// configuration and ssh-agent.
+    pub ec2_identity_files: Vec<String>,
+
+    /// Only offer the configured identity files, even if ssh-agent holds other keys
+    pub identities_only: bool,
+
+    /// Whether the instance hop runs on the bastion or locally through a ProxyCommand
+    pub topology: Topology,
+
+    /// How ssh checks the host keys of the bastion and instances
+    pub host_key_checking: HostKeyChecking,
+
+    /// Authenticate with an ephemeral key pushed by EC2 Instance Connect
+    pub instance_connect: bool,
+
+    /// How to have ephemeral keys signed, when authenticating with certificates
+    pub certificate: Option<Certificate>,
+
+    /// Grant ingress to your ip for the duration of every connection, revoking it on exit
+    pub auto_grant: bool,
+
+    /// How many seconds instances and tasks looked up in AWS are cached for. 0 disables the cache.
+    pub cache_ttl: u64,
+
+    /// How many seconds a single AWS request may take before it is abandoned
+    pub request_timeout: u64,
+
+    /// How many times an AWS request which was throttled, timed out or failed to reach AWS is
+    /// attempted before giving up
+    pub max_attempts: u32,
+
+    /// Where AWS credentials for this profile come from
+    pub credentials: Credentials,
+}
+
+impl Profile {
+    /// How to reach instances with this profile, before any command line overrides
+    pub fn ssh_settings(&self) -> anyhow::Result<SshSettings> {
+        Ok(SshSettings {
+            dns_name: self.dns_name.clone(),
+            bastion_port: self.bastion_port,
+            bastion_user: self.bastion_user.clone(),
+            ec2_user: self.ec2_user.clone(),
+            bastion_identity_files: self.bastion_identity_files.clone(),
+            ec2_identity_files: self.ec2_identity_files.clone(),
+            identities_only: self.identities_only,
+            topology: self.topology,
+            known_hosts_file: KnownHosts::managed()
+                .ok_or_else(|| anyhow::anyhow!("Unable to determine data directory."))?
+                .path()
+                .display()
+                .to_string(),
+            host_key_checking: self.host_key_checking,
+            instance_connect: self.instance_connect,
+            certificate: self.certificate.clone(),
+            certificate_file: None,
+        })
+    }
+}
+
+/// A profile as written in a single configuration source, where any setting may be absent
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PartialProfile {
+    /// Name of a profile to inherit settings from
+    pub extends: Option<String>,
+    pub aws_profile: Option<String>,
+    pub aws_region: Option<String>,
+    pub security_group_id: Option<String>,
+    pub dns_name: Option<String>,
+    pub bastion_port: Option<u16>,
+    pub bastion_user: Option<String>,
+    pub ec2_user: Option<String>,
+    /// Identity files used for both hops, unless overridden for a hop
+    pub identity_file: Option<Identities>,
+    pub bastion_identity_file: Option<Identities>,
+    pub ec2_identity_file: Option<Identities>,
+    pub identities_only: Option<bool>,
+    pub topology: Option<Topology>,
+    pub host_key_checking: Option<HostKeyChecking>,
+    pub instance_connect: Option<bool>,
+    pub certificate_signer: Option<Signer>,
+    pub certificate_validity: Option<String>,
+    pub certificate_principals: Option<Vec<String>>,
+    pub auto_grant: Option<bool>,
+    pub cache_ttl: Option<u64>,
+    pub request_timeout: Option<u64>,
+    pub max_attempts: Option<u32>,
+    pub credentials: Option<Credentials>,
+}
+
+impl PartialProfile {
+    /// The first of our settings which runs a command, if any
+    fn command_key(&self) -> Option<&'static str> {
+        if let Some(Credentials::Process { .. }) = self.credentials {
+            return Some("credentials");
+        }
+
+        match self.certificate_signer {
+            Some(Signer::Command { .. }) => Some("certificate_signer"),
+            _ => None,
+        }
+    }
+
+    /// Overlay the settings present in other on top of our own
+    fn merge(&mut self, other: &PartialProfile) {
+        let other = other.clone();
+
+        self.extends = other.extends.or_else(|| self.extends.take());
+        self.aws_profile = other.aws_profile.or_else(|| self.aws_profile.take());
+        self.aws_region = other.aws_region.or_else(|| self.aws_region.take());
+        self.security_group_id = other
+            .security_group_id
+            .or_else(|| self.security_group_id.take());
+        self.dns_name = other.dns_name.or_else(|| self.dns_name.take());
+        self.bastion_port = other.bastion_port.or(self.bastion_port);
+        self.bastion_user = other.bastion_user.or_else(|| self.bastion_user.take());
+        self.ec2_user = other.ec2_user.or_else(|| self.ec2_user.take());
+        self.identity_file = other.identity_file.or_else(|| self.identity_file.take());
+        self.bastion_identity_file = other
+            .bastion_identity_file
+            .or_else(|| self.bastion_identity_file.take());
+        self.ec2_identity_file = other
+            .ec2_identity_file
+            .or_else(|| self.ec2_identity_file.take());
+        self.identities_only = other.identities_only.or(self.identities_only);
+        self.topology = other.topology.or(self.topology);
+        self.host_key_checking = other.host_key_checking.or(self.host_key_checking);
+        self.instance_connect = other.instance_connect.or(self.instance_connect);
+        self.certificate_signer = other
+            .certificate_signer
+            .or_else(|| self.certificate_signer.take());
+        self.certificate_validity = other
+            .certificate_validity
+            .or_else(|| self.certificate_validity.take());
+        self.certificate_principals = other
+            .certificate_principals
+            .or_else(|| self.certificate_principals.take());
+        self.auto_grant = other.auto_grant.or(self.auto_grant);
+        self.cache_ttl = other.cache_ttl.or(self.cache_ttl);
+        self.request_timeout = other.request_timeout.or(self.request_timeout);
+        self.max_attempts = other.max_attempts.or(self.max_attempts);
+        self.credentials = other.credentials.or_else(|| self.credentials.take());
+    }
+
+    /// Each setting with its value formatted for display, in declaration order
+    fn entries(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![
+            ("aws_profile", self.aws_profile.clone()),
+            ("aws_region", self.aws_region.clone()),
+            ("security_group_id", self.security_group_id.clone()),
+            ("dns_name", self.dns_name.clone()),
+            (
+                "bastion_port",
+                self.bastion_port.map(|port| port.to_string()),
+            ),
+            ("bastion_user", self.bastion_user.clone()),
+            ("ec2_user", self.ec2_user.clone()),
+            (
+                "identity_file",
+                self.identity_file.as_ref().map(|files| files.to_string()),
+            ),
+            (
+                "bastion_identity_file",
+                self.bastion_identity_file
+                    .as_ref()
+                    .map(|files| files.to_string()),
+            ),
+            (
+                "ec2_identity_file",
+                self.ec2_identity_file
+                    .as_ref()
+                    .map(|files| files.to_string()),
+            ),
+            (
+                "identities_only",
+                self.identities_only.map(|only| only.to_string()),
+            ),
+            (
+                "topology",
+                self.topology.map(|topology| topology.to_string()),
+            ),
+            (
+                "host_key_checking",
+                self.host_key_checking.map(|checking| checking.to_string()),
+            ),
+            (
+                "instance_connect",
+                self.instance_connect.map(|connect| connect.to_string()),
+            ),
+            (
+                "certificate_signer",
+                self.certificate_signer
+                    .as_ref()
+                    .map(|signer| signer.to_string()),
+            ),
+            ("certificate_validity", self.certificate_validity.clone()),
+            (
+                "certificate_principals",
+                self.certificate_principals
+                    .as_ref()
+                    .map(|principals| principals.join(", ")),
+            ),
+            ("auto_grant", self.auto_grant.map(|grant| grant.to_string())),
+            ("cache_ttl", self.cache_ttl.map(|ttl| ttl.to_string())),
+            (
+                "request_timeout",
+                self.request_timeout.map(|timeout| timeout.to_string()),
+            ),
+            (
+                "max_attempts",
+                self.max_attempts.map(|attempts| attempts.to_string()),
+            ),
+            (
+                "credentials",
+                self.credentials
+                    .as_ref()
+                    .map(|credentials| credentials.to_string()),
+            ),
+        ]
+    }
+
+    fn validate(self, name: &str) -> Result<Profile, Error> {
+        fn require<T>(value: Option<T>, profile: &str, key: &'static str) -> Result<T, Error> {
+            value.ok_or_else(|| Error::MissingKey {
+                profile: profile.into(),
+                key,
+            })
+        }
+
+        let identity_file = self.identity_file;
+        let bastion_user = require(self.bastion_user, name, "bastion_user")?;
+        let ec2_user = require(self.ec2_user, name, "ec2_user")?;
+
+        let certificate = match self.certificate_signer {
+            Some(signer) => Some(Certificate {
+                signer: match signer {
+                    Signer::Local { ca_key } => Signer::Local {
+                        ca_key: expand_path(&ca_key)?,
+                    },
+                    signer => signer,
+                },
+                validity: self
+                    .certificate_validity
+                    .unwrap_or_else(|| signer::DEFAULT_VALIDITY.into()),
+                principals: self.certificate_principals.unwrap_or_else(|| {
+                    let mut principals = vec![bastion_user.clone(), ec2_user.clone()];
+                    principals.dedup();
+                    principals
+                }),
+            }),
+            None => None,
+        };
+
+        Ok(Profile {
+            aws_profile: require(self.aws_profile, name, "aws_profile")?,
+            aws_region: require(self.aws_region, name, "aws_region")?,
+            security_group_id: require(self.security_group_id, name, "security_group_id")?,
+            dns_name: require(self.dns_name, name, "dns_name")?,
+            bastion_port: require(self.bastion_port, name, "bastion_port")?,
+            bastion_user,
+            ec2_user,
+            bastion_identity_files: expand_paths(
+                self.bastion_identity_file.or_else(|| identity_file.clone()),
+            )?,
+            ec2_identity_files: expand_paths(self.ec2_identity_file.or(identity_file))?,
+            identities_only: self.identities_only.unwrap_or_default(),
+            topology: self.topology.unwrap_or_default(),
+            host_key_checking: self.host_key_checking.unwrap_or_default(),
+            instance_connect: self.instance_connect.unwrap_or_default(),
+            certificate,
+            auto_grant: self.auto_grant.unwrap_or_default(),
+            cache_ttl: self.cache_ttl.unwrap_or(DEFAULT_CACHE_TTL),
+            request_timeout: self.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+            // Every request is attempted at least once
+            max_attempts: self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            credentials: self.credentials.unwrap_or_default(),
+        })
+    }
+}
+
+/// One or more identity files
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Identities {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl fmt::Display for Identities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Identities::One(file) => write!(f, "{}", file),
+            Identities::Many(files) => write!(f, "{}", files.join(", ")),
+        }
+    }
+}
+
+fn expand_paths(identities: Option<Identities>) -> Result<Vec<String>, Error> {
+    let files = match identities {
+        None => Vec::new(),
+        Some(Identities::One(file)) => vec![file],
+        Some(Identities::Many(files)) => files,
+    };
+
+    files.iter().map(|file| expand_path(file)).collect()
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum Credentials {
+    /// Read AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN from the environment
+    Environment,
+
+    /// Use aws_profile, following any role_arn, sso_start_url or credential_process settings
+    /// for it in your ~/.aws/config file
+    #[default]
+    Profile,
+
+    /// Assume a role using the credentials of another profile, prompting for an MFA code if
+    /// mfa_serial is set
+    AssumeRole {
+        role_arn: String,
+        /// Defaults to aws_profile
+        source_profile: Option<String>,
+        mfa_serial: Option<String>,
+        external_id: Option<String>,
+        duration_seconds: Option<i64>,
+    },
+
+    /// Exchange the token cached by `aws sso login` for role credentials
+    Sso {
+        start_url: String,
+        /// Defaults to aws_region
+        region: Option<String>,
+        account_id: String,
+        role_name: String,
+    },
+
+    /// Run a command which prints credentials in the credential_process json format
+    Process { command: String },
+}
+
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Environment => write!(f, "environment"),
+            Credentials::Profile => write!(f, "profile"),
+            Credentials::AssumeRole { role_arn, .. } => write!(f, "assume_role {}", role_arn),
+            Credentials::Sso {
+                account_id,
+                role_name,
+                ..
+            } => write!(f, "sso {} in {}", role_name, account_id),
+            Credentials::Process { command } => write!(f, "process `{}`", command),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        expand_path, expand_path_with, Error, Identities, Layer, PartialProfile, Settings,
+    };
+
+    fn base() -> PartialProfile {
+        PartialProfile {
+            aws_profile: Some("default".into()),
+            aws_region: Some("us-east-1".into()),
+            security_group_id: Some("sg-12345678".into()),
+            dns_name: Some("bastion.example.io".into()),
+            bastion_port: Some(22),
+            bastion_user: Some("bastion-user".into()),
+            ec2_user: Some("ec2-user".into()),
+            identity_file: Some(Identities::One("~/.ssh/id_rsa".into())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn project_files_may_not_run_commands() {
+        let path =
+            std::env::temp_dir().join(format!("heimdallr-project-{}.toml", std::process::id()));
+
+        std::fs::write(
+            &path,
+            "[profiles.default.credentials]\nsource = \"process\"\ncommand = \"touch /tmp/pwned\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Layer::from_project_file(&path),
+            Err(Error::UntrustedCommand { source_name, key })
+                if source_name == path.display().to_string() && key == "profiles.default.credentials"
+        ));
+
+        std::fs::write(
+            &path,
+            "[defaults.certificate_signer]\ntype = \"command\"\ncommand = \"sign\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Layer::from_project_file(&path),
+            Err(Error::UntrustedCommand { key, .. }) if key == "defaults.certificate_signer"
+        ));
+
+        std::fs::write(&path, "[defaults]\nec2_user = \"ubuntu\"\n").unwrap();
+        assert!(Layer::from_project_file(&path).is_ok());
+
+        // An explicitly passed file is trusted
+        std::fs::write(
+            &path,
+            "[profiles.default.credentials]\nsource = \"process\"\ncommand = \"creds\"\n",
+        )
+        .unwrap();
+        assert!(Layer::from_file(&path, true).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn profiles_are_merged_over_defaults_from_every_layer() {
+        let mut global = Layer {
+            defaults: base(),
+            ..Default::default()
+        };
+        global.profiles.insert(
+            "staging".into(),
+            PartialProfile {
+                dns_name: Some("bastion-staging.example.io".into()),
+                ..Default::default()
+            },
+        );
+
+        let environment = Layer {
+            defaults: PartialProfile {
+                ec2_user: Some("ubuntu".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let settings = Settings {
+            layers: vec![global, environment],
+        };
+        let profile = settings.profile("staging").unwrap();
+
+        assert_eq!("bastion-staging.example.io", profile.dns_name);
+        assert_eq!("ubuntu", profile.ec2_user);
+        assert_eq!("bastion-user", profile.bastion_user);
+        assert!(!profile.auto_grant);
+    }
+
+    #[test]
+    fn later_defaults_win_over_earlier_profiles() {
+        let mut global = Layer {
+            defaults: base(),
+            ..Default::default()
+        };
+        global.profiles.insert(
+            "staging".into(),
+            PartialProfile {
+                ec2_user: Some("admin".into()),
+                bastion_user: Some("jump".into()),
+                ..Default::default()
+            },
+        );
+
+        let mut environment = Layer {
+            defaults: PartialProfile {
+                ec2_user: Some("ubuntu".into()),
+                bastion_user: Some("bastion".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        environment.profiles.insert(
+            "staging".into(),
+            PartialProfile {
+                bastion_user: Some("staging-jump".into()),
+                ..Default::default()
+            },
+        );
+
+        let settings = Settings {
+            layers: vec![global, environment],
+        };
+        let profile = settings.profile("staging").unwrap();
+
+        assert_eq!("ubuntu", profile.ec2_user);
+        assert_eq!("staging-jump", profile.bastion_user);
+    }
+
+    #[test]
+    fn identity_files_fall_back_per_hop() {
+        let mut layer = Layer::default();
+        layer.profiles.insert(
+            "agent".into(),
+            PartialProfile {
+                identity_file: None,
+                ..base()
+            },
+        );
+        layer.profiles.insert(
+            "split".into(),
+            PartialProfile {
+                ec2_identity_file: Some(Identities::Many(vec![
+                    "/keys/one".into(),
+                    "/keys/two".into(),
+                ])),
+                ..base()
+            },
+        );
+
+        let settings = Settings {
+            layers: vec![layer],
+        };
+
+        let agent = settings.profile("agent").unwrap();
+        assert!(agent.bastion_identity_files.is_empty());
+        assert!(agent.ec2_identity_files.is_empty());
+
+        let split = settings.profile("split").unwrap();
+        assert_eq!(
+            vec![expand_path("~/.ssh/id_rsa").unwrap()],
+            split.bastion_identity_files
+        );
+        assert_eq!(vec!["/keys/one", "/keys/two"], split.ec2_identity_files);
+    }
+
+    #[test]
+    fn missing_settings_name_the_profile_and_key() {
+        let mut layer = Layer::default();
+        layer.profiles.insert(
+            "staging".into(),
+            PartialProfile {
+                dns_name: None,
+                ..base()
+            },
+        );
+
+        let settings = Settings {
+            layers: vec![layer],
+        };
+
+        match settings.profile("staging") {
+            Err(Error::MissingKey { profile, key }) => {
+                assert_eq!("staging", profile);
+                assert_eq!("dns_name", key);
+            }
+            _ => panic!("expected a missing key error"),
+        }
+
+        assert!(matches!(
+            settings.profile("production"),
+            Err(Error::MissingProfile { .. })
+        ));
+    }
+
+    #[test]
+    fn explain_reports_the_source_of_each_setting() {
+        let mut global = Layer {
+            source: "global".into(),
+            defaults: base(),
+            ..Default::default()
+        };
+        global
+            .profiles
+            .insert("staging".into(), PartialProfile::default());
+
+        let mut environment = Layer {
+            source: "environment".into(),
+            ..Default::default()
+        };
+        environment.profiles.insert(
+            "staging".into(),
+            PartialProfile {
+                dns_name: Some("bastion-staging.example.io".into()),
+                ..Default::default()
+            },
+        );
+
+        let settings = Settings {
+            layers: vec![global, environment],
+        };
+        let origins = settings.explain("staging").unwrap();
+
+        let dns_name = origins.iter().find(|o| o.key == "dns_name").unwrap();
+        assert_eq!(
+            Some("bastion-staging.example.io".to_string()),
+            dns_name.value
+        );
+        assert_eq!(
+            Some("environment [profiles.staging]".to_string()),
+            dns_name.source
+        );
+
+        let ec2_user = origins.iter().find(|o| o.key == "ec2_user").unwrap();
+        assert_eq!(Some("global [defaults]".to_string()), ec2_user.source);
+
+        let auto_grant = origins.iter().find(|o| o.key == "auto_grant").unwrap();
+        assert_eq!(Some("false".to_string()), auto_grant.value);
+        assert_eq!(None, auto_grant.source);
+    }
+
+    #[test]
+    fn paths_expand_home_and_environment_variables() {
+        let home = dirs::home_dir().unwrap().display().to_string();
+        let lookup = |name: &str| match name {
+            "HOME" => Some("/home/me".to_string()),
+            "KEY" => Some("id_ed25519".to_string()),
+            _ => None,
+        };
+
+        assert_eq!(
+            format!("{}/.ssh/id_rsa", home),
+            expand_path_with("~/.ssh/id_rsa", lookup).unwrap()
+        );
+        assert_eq!(
+            "/home/me/.ssh/id_ed25519",
+            expand_path_with("$HOME/.ssh/${KEY}", lookup).unwrap()
+        );
+        assert_eq!(
+            "~user/key$",
+            expand_path_with("~user/key$", lookup).unwrap()
+        );
+        assert_eq!(
+            "/keys/${KEY",
+            expand_path_with("/keys/${KEY", lookup).unwrap()
+        );
+        assert!(matches!(
+            expand_path_with("${UNDEFINED}/key", lookup),
+            Err(Error::UndefinedVariable { .. })
+        ));
+    }
+
+    #[test]
+    fn profiles_inherit_through_multiple_levels() {
+        let mut layer = Layer::default();
+        layer.profiles.insert("base".into(), base());
+        layer.profiles.insert(
+            "staging".into(),
+            PartialProfile {
+                extends: Some("base".into()),
+                aws_profile: Some("staging".into()),
+                ec2_user: Some("ubuntu".into()),
+                ..Default::default()
+            },
+        );
+        layer.profiles.insert(
+            "staging-eu".into(),
+            PartialProfile {
+                extends: Some("staging".into()),
+                aws_region: Some("eu-west-1".into()),
+                ..Default::default()
+            },
+        );
+
+        let settings = Settings {
+            layers: vec![layer],
+        };
+        let profile = settings.profile("staging-eu").unwrap();
+
+        assert_eq!("eu-west-1", profile.aws_region);
+        assert_eq!("staging", profile.aws_profile);
+        assert_eq!("ubuntu", profile.ec2_user);
+        assert_eq!("bastion.example.io", profile.dns_name);
+    }
+
+    #[test]
+    fn inheritance_cycles_and_missing_parents_are_reported() {
+        let mut layer = Layer::default();
+        for (name, parent) in &[("a", "b"), ("b", "c"), ("c", "a"), ("d", "missing")] {
+            layer.profiles.insert(
+                name.to_string(),
+                PartialProfile {
+                    extends: Some(parent.to_string()),
+                    ..base()
+                },
+            );
+        }
+
+        let settings = Settings {
+            layers: vec![layer],
+        };
+
+        match settings.profile("a") {
+            Err(Error::CyclicInheritance(chain)) => assert_eq!("a -> b -> c -> a", chain),
+            _ => panic!("expected a cycle to be detected"),
+        }
+
+        assert!(matches!(
+            settings.profile("d"),
+            Err(Error::MissingParent { .. })
+        ));
+    }
+
+    #[test]
+    fn extends_under_defaults_is_reported() {
+        let settings = Settings {
+            layers: vec![Layer {
+                source: "heimdallr.toml".into(),
+                defaults: PartialProfile {
+                    extends: Some("base".into()),
+                    ..base()
+                },
+                ..Default::default()
+            }],
+        };
+
+        let errors = settings.check_defaults();
+        assert_eq!(1, errors.len());
+        assert!(
+            matches!(&errors[0], Error::ExtendsInDefaults(source) if source == "heimdallr.toml")
+        );
+    }
+
+    #[test]
+    fn aliases_from_later_layers_win() {
+        let mut global = Layer::default();
+        global
+            .aliases
+            .insert("api".into(), "prod-cluster#api".into());
+        global.aliases.insert("db".into(), "prod/db".into());
+        let mut project = Layer::default();
+        project
+            .aliases
+            .insert("api".into(), "prod-cluster#api#web".into());
+
+        let settings = Settings {
+            layers: vec![global, project],
+        };
+
+        assert_eq!(Some("prod-cluster#api#web"), settings.alias("API"));
+        assert_eq!(Some("prod/db"), settings.alias("db"));
+        assert_eq!(None, settings.alias("worker"));
+    }
 }
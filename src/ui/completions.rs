@@ -0,0 +1,285 @@
+use crate::application::{connect, inventory, Clients};
+use crate::domain::cache::Cache;
+use crate::settings::Settings;
+use anyhow::Result;
+use std::env;
+use std::io::Write;
+use std::os::unix::process::CommandExt;
+use std::process::{self, Stdio};
+use std::time::Duration;
+use structopt::clap::{App, Shell};
+use tokio::time;
+
+/// How long completing a target may wait on AWS when nothing is cached
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Options of connect which take a value, so the word after them is not a target
+const CONNECT_VALUE_OPTIONS: &str = "-d|--dns-name|-p|--bastion-port|-u|--bastion-user|-e|--ec2-user|-i|--identity-file|--bastion-identity-file|--ec2-identity-file|--pick|--instance-id|--task-id|--via";
+
+const BASH: &str = r#"
+_heimdallr_dynamic() {
+    local i cur prev args=()
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+
+    # Options before the command are heimdallr's own. Those after it belong to the command.
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            -p|--profile|-c|--config)
+                if [[ $((i + 1)) -eq ${COMP_CWORD} ]]; then
+                    if [[ ${COMP_WORDS[i]} == -p || ${COMP_WORDS[i]} == --profile ]]; then
+                        COMPREPLY=($(heimdallr __complete profiles "${cur}" 2>/dev/null </dev/null))
+                        return 0
+                    fi
+                    break
+                fi
+                args+=("${COMP_WORDS[i]}" "${COMP_WORDS[i+1]}")
+                ((i++))
+                ;;
+            connect)
+                case "${prev}" in
+                    VALUE_OPTIONS) ;;
+                    *)
+                        if [[ ${cur} != -* ]]; then
+                            COMPREPLY=($(heimdallr __complete "${args[@]}" targets "${cur}" 2>/dev/null </dev/null))
+                            return 0
+                        fi
+                        ;;
+                esac
+                break
+                ;;
+            -*) ;;
+            *) break ;;
+        esac
+    done
+
+    _heimdallr "$@"
+}
+
+complete -F _heimdallr_dynamic -o bashdefault -o default heimdallr
+"#;
+
+const ZSH: &str = r#"(( $+functions[_heimdallr_profiles] )) ||
+_heimdallr_profiles() {
+    local -a profiles
+    profiles=(${(f)"$(heimdallr __complete profiles "$PREFIX" 2>/dev/null </dev/null)"})
+    compadd -a profiles
+}
+(( $+functions[_heimdallr_targets] )) ||
+_heimdallr_targets() {
+    local -a tokens args targets
+    local i
+    tokens=(${(z)BUFFER})
+    for ((i = 2; i <= $#tokens; i++)); do
+        case $tokens[i] in
+            (-p|--profile|-c|--config) args+=($tokens[i] $tokens[i+1]) ;;
+            (connect) break ;;
+        esac
+    done
+    targets=(${(f)"$(heimdallr __complete $args targets "$PREFIX" 2>/dev/null </dev/null)"})
+    compadd -a targets
+}
+
+"#;
+
+const FISH: &str = r#"
+function __heimdallr_complete
+    set -l tokens (commandline -opc)
+    set -l args
+    for i in (seq 2 (count $tokens))
+        switch $tokens[$i]
+            case -p --profile -c --config
+                set args $args $tokens[$i] $tokens[(math $i + 1)]
+            case connect
+                break
+        end
+    end
+    heimdallr __complete $args $argv (commandline -ct) 2>/dev/null </dev/null
+end
+
+complete -c heimdallr -n "__fish_use_subcommand" -s p -l profile -r -f -a "(__heimdallr_complete profiles)"
+complete -c heimdallr -n "__fish_seen_subcommand_from connect" -f -a "(__heimdallr_complete targets)"
+"#;
+
+/// Write the completion script for the shell. clap completes commands and options, and the
+/// script asks heimdallr __complete for profiles and connect targets.
+pub fn script(app: &mut App, shell: Shell, out: &mut impl Write) -> Result<()> {
+    let mut generated = Vec::new();
+    app.gen_completions_to("heimdallr", shell, &mut generated);
+    let generated = String::from_utf8(generated)?;
+
+    let script = match shell {
+        Shell::Bash => bash(&generated),
+        Shell::Zsh => zsh(&generated),
+        Shell::Fish => generated + FISH,
+        _ => generated,
+    };
+
+    out.write_all(script.as_bytes())?;
+    Ok(())
+}
+
+fn bash(generated: &str) -> String {
+    let completion = "complete -F _heimdallr -o bashdefault -o default heimdallr\n";
+    generated.replace(completion, "") + &BASH.replace("VALUE_OPTIONS", CONNECT_VALUE_OPTIONS)
+}
+
+fn zsh(generated: &str) -> String {
+    let mut profile = ["'-p+[", "'--profile=["];
+    let mut lines = Vec::new();
+
+    for line in generated.lines() {
+        let mut line = line.to_string();
+
+        // The global profile option comes first. Connect has a -p of its own.
+        for prefix in profile.iter_mut() {
+            if !prefix.is_empty() && line.starts_with(*prefix) {
+                line = line.replacen("]' \\", "]:profile:_heimdallr_profiles' \\", 1);
+                *prefix = "";
+            }
+        }
+
+        if line.starts_with("':target -- ") {
+            line = line.replacen(":_files' \\", ":_heimdallr_targets' \\", 1);
+        }
+
+        if line == "_heimdallr \"$@\"" {
+            lines.push(ZSH.to_string());
+        }
+
+        lines.push(line);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Print the candidates starting with the word being completed, one per line. Completion must not
+/// get in the way, so anything which cannot be looked up is left out rather than reported.
+///
+/// Unless told to wait, a lookup too slow to wait for is finished by another heimdallr in the
+/// background, so later completions find it cached.
+pub async fn complete(
+    settings: &Settings,
+    profile: &str,
+    kind: &str,
+    word: &str,
+    wait: bool,
+) -> Result<()> {
+    let candidates = match kind {
+        "profiles" => settings.profile_names(),
+        _ => targets(settings, profile, word, wait).await,
+    };
+
+    for candidate in candidates.iter().filter(|c| c.starts_with(word)) {
+        println!("{}", candidate);
+    }
+
+    Ok(())
+}
+
+/// Aliases, instance names and services, or the containers of the service once cluster#service#
+/// is given. Lookups go through the cache, so only the first completion waits on AWS.
+async fn targets(settings: &Settings, profile: &str, word: &str, wait: bool) -> Vec<String> {
+    let mut targets = settings.alias_names();
+    let lookup = look_up(settings, profile, word);
+
+    let found = match wait {
+        true => Ok(lookup.await),
+        false => time::timeout(LOOKUP_TIMEOUT, lookup).await,
+    };
+
+    match found {
+        Ok(Ok(found)) => targets.extend(found),
+        Ok(Err(_)) => {}
+        Err(_) => finish_in_background(),
+    }
+
+    targets
+}
+
+/// Run this completion again, waiting on the lookup however long it takes. It runs in a process
+/// group of its own, so interrupting the shell does not interrupt it.
+fn finish_in_background() {
+    let exe = match env::current_exe() {
+        Ok(exe) => exe,
+        Err(_) => return,
+    };
+
+    let _ = process::Command::new(exe)
+        .arg("__complete")
+        .arg("--wait")
+        .args(env::args().skip(2))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn();
+}
+
+async fn look_up(settings: &Settings, profile: &str, word: &str) -> Result<Vec<String>> {
+    let profile_settings = settings.profile(profile)?;
+    let clients = Clients::new(profile, &profile_settings)?;
+    let cache = Cache::managed(
+        profile,
+        &profile_settings.aws_region,
+        profile_settings.cache_ttl,
+        false,
+    );
+
+    let connect_handler = connect::Handler::new(
+        &clients.ecs,
+        &clients.ec2,
+        &clients.instance_connect,
+        &cache,
+    );
+
+    if let [cluster, service, _] = word.split('#').collect::<Vec<&str>>()[..] {
+        return Ok(connect_handler
+            .container_names(cluster, service)
+            .await?
+            .iter()
+            .map(|container| format!("{}#{}#{}", cluster, service, container))
+            .collect());
+    }
+
+    let inventory_handler = inventory::Handler::new(&clients.ec2, &clients.ecs, &cache);
+
+    let mut targets = connect_handler.instance_names().await?;
+    targets.extend(inventory_handler.service_names().await?);
+
+    Ok(targets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::script;
+    use structopt::clap::Shell;
+    use structopt::StructOpt;
+
+    #[test]
+    fn scripts_complete_profiles_and_targets() {
+        let generate = |shell| {
+            let mut out = Vec::new();
+            script(&mut crate::Heimdallr::clap(), shell, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        let bash = generate(Shell::Bash);
+        assert!(bash.contains("__complete profiles"));
+        assert!(bash.contains("__complete \"${args[@]}\" targets"));
+        assert!(
+            bash.ends_with("complete -F _heimdallr_dynamic -o bashdefault -o default heimdallr\n")
+        );
+        assert!(!bash.contains("complete -F _heimdallr -o"));
+        // The word after --via is a bastion, not a target
+        assert!(bash.contains("|--via)"));
+
+        let zsh = generate(Shell::Zsh);
+        assert_eq!(2, zsh.matches(":profile:_heimdallr_profiles'").count());
+        assert_eq!(1, zsh.matches(":_heimdallr_targets'").count());
+        assert!(zsh.ends_with("_heimdallr \"$@\"\n"));
+
+        let fish = generate(Shell::Fish);
+        assert!(fish.contains("(__heimdallr_complete targets)"));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::application::discovery::Handler;
+use crate::credentials;
+use crate::settings::{self, Settings};
+use anyhow::{anyhow, Context, Result};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, Select};
+use prettytable::{format, row, Table};
+use rusoto_core::{region, HttpClient};
+use rusoto_ec2::Ec2Client;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use toml::value::{Table as TomlTable, Value};
+
+/// Print the effective settings of a profile and where each of them came from
+pub fn show(settings: &Settings, profile: &str) -> Result<()> {
+    let format = format::FormatBuilder::new()
+        .column_separator('│')
+        .borders('│')
+        .separators(
+            &[format::LinePosition::Title],
+            format::LineSeparator::new('─', '┼', '├', '┤'),
+        )
+        .padding(1, 1)
+        .build();
+    let mut table = Table::new();
+    table.set_format(format);
+    table.set_titles(row![Fgb->"Setting", Fgb->"Value", Fgb->"Source"]);
+
+    for origin in settings.explain(profile)? {
+        table.add_row(row![
+            Fbb->origin.key,
+            Fyb->origin.value.unwrap_or_else(|| "(not set)".into()),
+            Fcb->origin.source.unwrap_or_else(|| "(default)".into())
+        ]);
+    }
+
+    table.printstd();
+
+    Ok(())
+}
+
+/// Check every profile resolves to a complete set of settings with a usable identity file
+pub fn validate(settings: &Settings) -> Result<()> {
+    let names = settings.profile_names();
+    if names.is_empty() {
+        return Err(anyhow!("No profiles are configured"));
+    }
+
+    let misplaced = settings.check_defaults();
+    for e in &misplaced {
+        println!("✘ [defaults]: {}", e);
+    }
+
+    let mut invalid = 0;
+    for name in &names {
+        let profile = settings.profile(name).and_then(|profile| {
+            for file in profile
+                .bastion_identity_files
+                .iter()
+                .chain(profile.ec2_identity_files.iter())
+            {
+                settings::check_identity_file(file)?;
+            }
+            Ok(profile)
+        });
+
+        match profile {
+            Ok(_) => println!("✔ {}", name),
+            Err(e) => {
+                invalid += 1;
+                println!("✘ {}: {}", name, e);
+            }
+        }
+    }
+
+    match invalid {
+        0 if misplaced.is_empty() => Ok(()),
+        0 => Err(anyhow!("[defaults] sets settings which have no effect")),
+        _ => Err(anyhow!(
+            "{} of {} profiles are invalid",
+            invalid,
+            names.len()
+        )),
+    }
+}
+
+/// Walk through creating a new profile, discovering the bastion server and its security group
+/// from the chosen AWS account
+pub async fn init(settings: &Settings, config_file: Option<PathBuf>) -> Result<()> {
+    let path = config_file
+        .or_else(settings::global_config_file)
+        .ok_or_else(|| anyhow!("Unable to determine configuration directory."))?;
+    let theme = ColorfulTheme::default();
+
+    let aws_profiles = credentials::aws_profile_names();
+    let aws_profile = if aws_profiles.is_empty() {
+        Input::<String>::with_theme(&theme)
+            .with_prompt("AWS profile")
+            .default("default".into())
+            .interact_text()?
+    } else {
+        let selection = Select::with_theme(&theme)
+            .with_prompt("AWS profile")
+            .items(&aws_profiles)
+            .default(0)
+            .interact()
+            .context("Selection cancelled. Exiting.")?;
+        aws_profiles[selection].clone()
+    };
+
+    let aws_region = Input::<String>::with_theme(&theme)
+        .with_prompt("AWS region")
+        .default(
+            credentials::aws_profile_region(&aws_profile).unwrap_or_else(|| "us-east-1".into()),
+        )
+        .interact_text()?;
+
+    let region = aws_region.parse::<region::Region>()?;
+    let provider = credentials::Provider::for_aws_profile(&aws_profile, region.clone());
+    let ec2_client = Ec2Client::new_with(HttpClient::new()?, provider, region);
+    let handler = Handler::new(&ec2_client);
+
+    println!("Looking for bastion servers in {}...", aws_region);
+    let bastions = handler.bastion_candidates().await?;
+
+    let mut items: Vec<String> = bastions
+        .iter()
+        .map(|bastion| {
+            format!(
+                "{} ({}) @ {}",
+                bastion.name, bastion.instance_id, bastion.dns_name
+            )
+        })
+        .collect();
+    items.push("Enter manually".into());
+
+    let selection = Select::with_theme(&theme)
+        .with_prompt("Bastion server")
+        .items(&items)
+        .default(0)
+        .interact()
+        .context("Selection cancelled. Exiting.")?;
+
+    let (dns_name, group_ids) = match bastions.get(selection) {
+        Some(bastion) => (bastion.dns_name.clone(), bastion.security_group_ids.clone()),
+        None => (
+            Input::<String>::with_theme(&theme)
+                .with_prompt("Bastion host name")
+                .interact_text()?,
+            Vec::new(),
+        ),
+    };
+
+    let groups = handler.ssh_security_groups(group_ids).await?;
+    let security_group_id = if groups.is_empty() {
+        Input::<String>::with_theme(&theme)
+            .with_prompt("Security group id controlling ssh ingress to the bastion")
+            .interact_text()?
+    } else {
+        let items: Vec<String> = groups
+            .iter()
+            .map(|group| format!("{} ({})", group.group_id, group.name))
+            .collect();
+        let selection = Select::with_theme(&theme)
+            .with_prompt("Security group controlling ssh ingress to the bastion")
+            .items(&items)
+            .default(0)
+            .interact()
+            .context("Selection cancelled. Exiting.")?;
+        groups[selection].group_id.clone()
+    };
+
+    let bastion_port = Input::<u16>::with_theme(&theme)
+        .with_prompt("Bastion ssh port")
+        .default(22)
+        .interact_text()?;
+    let bastion_user = Input::<String>::with_theme(&theme)
+        .with_prompt("Bastion ssh user")
+        .default(std::env::var("USER").unwrap_or_else(|_| "ec2-user".into()))
+        .interact_text()?;
+    let ec2_user = Input::<String>::with_theme(&theme)
+        .with_prompt("Instance ssh user")
+        .default("ec2-user".into())
+        .interact_text()?;
+    let identity_file = Input::<String>::with_theme(&theme)
+        .with_prompt("ssh identity file (leave empty to use ssh-agent)")
+        .default("~/.ssh/id_rsa".into())
+        .allow_empty(true)
+        .interact_text()?;
+
+    let name = Input::<String>::with_theme(&theme)
+        .with_prompt("Name for this heimdallr profile")
+        .default(aws_profile.clone())
+        .interact_text()?;
+
+    let target = path.display().to_string();
+    if settings
+        .layers
+        .iter()
+        .any(|layer| layer.source == target && layer.profiles.contains_key(&name))
+    {
+        return Err(anyhow!("Profile {} already exists in {}", name, target));
+    }
+
+    if settings.profile_names().contains(&name)
+        && !Confirm::with_theme(&theme)
+            .with_prompt(format!(
+                "Profile {} is defined in another configuration file. Override it in {}?",
+                name, target
+            ))
+            .interact()?
+    {
+        return Err(anyhow!("Profile {} already exists", name));
+    }
+
+    let mut profile = TomlTable::new();
+    profile.insert("aws_profile".into(), Value::String(aws_profile));
+    profile.insert("aws_region".into(), Value::String(aws_region));
+    profile.insert("security_group_id".into(), Value::String(security_group_id));
+    profile.insert("dns_name".into(), Value::String(dns_name));
+    profile.insert("bastion_port".into(), Value::Integer(bastion_port.into()));
+    profile.insert("bastion_user".into(), Value::String(bastion_user));
+    profile.insert("ec2_user".into(), Value::String(ec2_user));
+    if !identity_file.is_empty() {
+        profile.insert("identity_file".into(), Value::String(identity_file));
+    }
+
+    let mut profiles = TomlTable::new();
+    profiles.insert(name.clone(), Value::Table(profile));
+    let mut document = TomlTable::new();
+    document.insert("profiles".into(), Value::Table(profiles));
+
+    let contents = toml::to_string(&document)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| write!(file, "\n{}", contents))
+        .with_context(|| format!("Unable to write {}", target))?;
+
+    println!("Added profile {} to {}", name, target);
+
+    Ok(())
+}
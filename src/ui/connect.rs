@@ -1,67 +1,381 @@
-use crate::application::connect::Handler;
-use anyhow::{Context, Result};
+use crate::application::connect::{Handler, InstanceLocation};
+use crate::application::error::Error;
+use crate::application::security_groups;
+use crate::domain::connections::{self, SshConnection, SshSettings};
+use crate::domain::fuzzy;
+use crate::domain::known_hosts::{self, HostKeyChecking, KnownHosts};
+use crate::domain::selection::Selection;
+use crate::domain::target::Target;
+use crate::keys::EphemeralKey;
+use crate::ui;
+use anyhow::{anyhow, Context, Result};
 use dialoguer::{theme::ColorfulTheme, Select};
+use std::io::{self, IsTerminal};
+use tokio::{process::Command, signal};
 
-pub async fn connect(
-    handler: Handler<'_>,
-    dns_name: String,
-    bastion_port: u16,
-    bastion_user: String,
-    ec2_user: String,
-    ssh_identity_file: String,
-    target: &str,
+pub fn connect(prepared: Prepared, choice: &dyn SshConnection, cmd: Vec<String>) {
+    println!("{}", choice.connection(&prepared.settings, cmd));
+
+    // The printed command is run after we exit, so its key has to outlive us
+    prepared.keep();
+}
+
+/// Run a session against the target, allowing our ip through the bastion security group for as
+/// long as the session is running.
+pub async fn connect_with_grant(
+    security_group_handler: security_groups::Handler<'_>,
+    security_group_id: String,
+    prepared: Prepared,
+    choice: &dyn SshConnection,
     cmd: Vec<String>,
+    dry_run: bool,
 ) -> Result<()> {
-    let choices = match target {
-        target if target.contains('#') => {
-            handler.ssh_connection_choices_for_service(target).await?
-        }
-        target if target.contains('@') => {
-            let parts: Vec<&str> = target.split('@').collect();
-            handler.ssh_connection_choices_for_host(parts[1]).await?
+    run_with_grant(
+        security_group_handler,
+        security_group_id,
+        choice.connection(&prepared.settings, cmd),
+        dry_run,
+    )
+    .await
+}
+
+/// Settings ready to connect with, and the ephemeral key they authenticate with, if any. The key
+/// is removed once this is dropped, unless it is kept.
+pub struct Prepared {
+    pub settings: SshSettings,
+    key: Option<EphemeralKey>,
+}
+
+impl Prepared {
+    /// Leave the key in place for a printed command to use
+    pub fn keep(self) {
+        if let Some(key) = self.key {
+            key.keep();
         }
-        _ => handler.ssh_connection_choices_for_host(target).await?,
-    };
+    }
+}
+
+/// Run the invocation, allowing our ip through the bastion security group for as long as it is
+/// running.
+pub async fn run_with_grant(
+    security_group_handler: security_groups::Handler<'_>,
+    security_group_id: String,
+    invocation: String,
+    dry_run: bool,
+) -> Result<()> {
+    let change = security_group_handler
+        .grant_access(security_group_id.clone(), Some("heimdallr session".into()))
+        .await?;
+
+    if dry_run {
+        return match change {
+            security_groups::Change::Planned(permission) => {
+                ui::security_groups::print_plan('+', &security_group_id, &permission);
+                println!("{}", invocation);
+                ui::security_groups::print_plan('-', &security_group_id, &permission);
+                Ok(())
+            }
+            _ => {
+                println!("{}", invocation);
+                Ok(())
+            }
+        };
+    }
+
+    let session = run(invocation).await;
+
+    // Only clean up after ourselves. If the ip was already allowed, someone else owns the rule.
+    if change != security_groups::Change::Applied {
+        return session;
+    }
+
+    let revoked = security_group_handler
+        .revoke_access(security_group_id.clone())
+        .await;
+
+    match (session, revoked) {
+        (session, Ok(_)) => session,
+        (Ok(()), Err(e)) => Err(e),
+        (Err(session), Err(revoke)) => Err(session.context(format!(
+            "The session failed, and removing your ip from {} also failed: {:#}",
+            security_group_id, revoke
+        ))),
+    }
+}
+
+/// The target the selection matches, asking which one when several do and stdin is a terminal
+pub async fn choose(
+    handler: &Handler<'_>,
+    selection: &Selection,
+) -> Result<Box<dyn SshConnection>> {
+    let choice = choose_from_choices(handler, selection).await?;
+
+    // A cached choice may have stopped since it was cached, in which case look again
+    if handler.cached() && stopped(handler, &selection.target, choice.as_ref()).await? {
+        return choose_from_choices(handler, selection).await;
+    }
+
+    Ok(choice)
+}
+
+/// Whether a previously looked up choice of the target has stopped running since, forgetting the
+/// cached choices of the target when it has
+pub async fn stopped(
+    handler: &Handler<'_>,
+    target: &Target,
+    choice: &dyn SshConnection,
+) -> Result<bool> {
+    if handler.still_running(target, choice).await? {
+        return Ok(false);
+    }
+
+    handler.forget(target);
+    Ok(true)
+}
+
+async fn choose_from_choices(
+    handler: &Handler<'_>,
+    selection: &Selection,
+) -> Result<Box<dyn SshConnection>> {
+    // Only the lookup is timed, not however long the pick below waits on the user
+    let choices = ui::timed(
+        &format!("Resolved {}", selection.target),
+        look_up(handler, &selection.target),
+    )
+    .await?;
+
+    let mut choices = selection.apply(choices)?;
 
     match choices.len() {
+        x if x > 1 && !io::stdin().is_terminal() => Err(Error::Ambiguous(format!(
+            "{} targets match. Choose one with --pick, --instance-id or --task-id:\n{}",
+            x,
+            listing(&choices)
+        ))
+        .into()),
         x if x > 1 => {
             let theme = ColorfulTheme::default();
             let mut selection = Select::with_theme(&theme);
             selection.with_prompt("Select the instance to connect to");
-            selection.items(choices.as_slice());
+            selection.items(&connections::aligned(&choices));
 
             let selection_choice = selection
                 .interact()
                 .context("Selection cancelled. Exiting.")?;
-            println!(
-                "{}",
-                &choices[selection_choice].connection(
-                    dns_name,
-                    bastion_port,
-                    bastion_user,
-                    ec2_user,
-                    ssh_identity_file,
-                    cmd
-                )
-            );
+
+            Ok(choices.swap_remove(selection_choice))
         }
-        x if x == 1 => {
-            println!(
-                "{}",
-                &choices[0].connection(
-                    dns_name,
-                    bastion_port,
-                    bastion_user,
-                    ec2_user,
-                    ssh_identity_file,
-                    cmd
-                )
-            );
+        1 => Ok(choices.remove(0)),
+        _ => Err(Error::NoMatch(selection.target.to_string()).into()),
+    }
+}
+
+/// The choices of the target, or of the name it is close to when nothing matches it
+async fn look_up(handler: &Handler<'_>, target: &Target) -> Result<Vec<Box<dyn SshConnection>>> {
+    let choices = handler.ssh_connection_choices(target).await?;
+    if !choices.is_empty() {
+        return Ok(choices);
+    }
+
+    match suggest(handler, target).await? {
+        Some(name) => handler.ssh_connection_choices(&Target::Name(name)).await,
+        None => Ok(choices),
+    }
+}
+
+/// Numbered candidates, in the order --pick index:N counts them
+fn listing(choices: &[Box<dyn SshConnection>]) -> String {
+    connections::aligned(choices)
+        .iter()
+        .enumerate()
+        .map(|(i, choice)| format!("  {}. {}", i + 1, choice))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Get ready to connect to the chosen target, returning the settings to connect with and the key
+/// they use
+pub async fn prepare(
+    handler: &Handler<'_>,
+    settings: &SshSettings,
+    choice: &dyn SshConnection,
+) -> Result<Prepared> {
+    check_topology(settings)?;
+    record_host_keys(handler, settings, choice).await?;
+
+    if !settings.instance_connect && settings.certificate.is_none() {
+        return Ok(Prepared {
+            settings: settings.clone(),
+            key: None,
+        });
+    }
+
+    let key = EphemeralKey::generate().await?;
+
+    if settings.instance_connect {
+        let bastion = bastion_location(handler, settings).await?;
+        let instance = InstanceLocation {
+            instance_id: choice.instance_id().into(),
+            availability_zone: choice.availability_zone().into(),
+        };
+
+        handler
+            .send_ssh_public_key(&bastion, &settings.bastion_user, &key.public_key)
+            .await?;
+        handler
+            .send_ssh_public_key(&instance, &settings.ec2_user, &key.public_key)
+            .await?;
+    }
+
+    let certificate_file = match &settings.certificate {
+        Some(certificate) => Some(certificate.sign(&key).await?.display().to_string()),
+        None => None,
+    };
+
+    Ok(Prepared {
+        settings: settings.with_session_key(
+            &key.private_key.display().to_string(),
+            certificate_file.as_deref(),
+        ),
+        key: Some(key),
+    })
+}
+
+/// The settings prepare would return, without making any changes
+pub fn planned(settings: &SshSettings) -> Result<Prepared> {
+    check_topology(settings)?;
+
+    if !settings.instance_connect && settings.certificate.is_none() {
+        return Ok(Prepared {
+            settings: settings.clone(),
+            key: None,
+        });
+    }
+
+    let key = EphemeralKey::planned()?;
+    let certificate_file = settings
+        .certificate
+        .as_ref()
+        .map(|_| key.certificate_path().display().to_string());
+
+    Ok(Prepared {
+        settings: settings.with_session_key(
+            &key.private_key.display().to_string(),
+            certificate_file.as_deref(),
+        ),
+        key: Some(key),
+    })
+}
+
+/// Refuse settings the topology cannot honour, rather than connecting without them
+pub fn check_topology(settings: &SshSettings) -> Result<()> {
+    match settings.needs_proxy_command() {
+        Some(setting) => Err(anyhow!(
+            "{} needs topology = \"proxy_command\", as ssh running on the bastion cannot use the keys and host keys kept on this machine",
+            setting
+        )),
+        None => Ok(()),
+    }
+}
+
+async fn bastion_location(
+    handler: &Handler<'_>,
+    settings: &SshSettings,
+) -> Result<InstanceLocation> {
+    handler
+        .instance_for_address(&settings.dns_name)
+        .await?
+        .ok_or_else(|| {
+            anyhow!(
+                "Unable to find the bastion instance for {}",
+                settings.dns_name
+            )
+        })
+}
+
+/// With console host key checking, record the keys the bastion and the chosen instance printed to
+/// their consoles on boot, replacing anything previously recorded for them.
+async fn record_host_keys(
+    handler: &Handler<'_>,
+    settings: &SshSettings,
+    choice: &dyn SshConnection,
+) -> Result<()> {
+    if settings.host_key_checking != HostKeyChecking::Console {
+        return Ok(());
+    }
+
+    let known_hosts = KnownHosts::new(&settings.known_hosts_file);
+
+    let bastion = bastion_location(handler, settings).await?;
+    let bastion_keys = handler.console_host_keys(&bastion.instance_id).await?;
+    known_hosts
+        .replace(
+            &known_hosts::host_pattern(&settings.dns_name, settings.bastion_port),
+            &bastion_keys,
+        )
+        .with_context(|| format!("Unable to write {}", settings.known_hosts_file))?;
+
+    let instance_keys = handler.console_host_keys(choice.instance_id()).await?;
+    known_hosts
+        .replace(choice.instance_id(), &instance_keys)
+        .with_context(|| format!("Unable to write {}", settings.known_hosts_file))?;
+
+    Ok(())
+}
+
+/// Offer the names of running instances close to a name which matched nothing
+async fn suggest(handler: &Handler<'_>, target: &Target) -> Result<Option<String>> {
+    let name = match target {
+        Target::Name(name) => name,
+        _ => return Ok(None),
+    };
+
+    let names = handler.instance_names().await?;
+    let matches = fuzzy::near_matches(name, &names);
+    if matches.is_empty() {
+        return Ok(None);
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(Error::NoMatch(format!(
+            "{}. Did you mean one of {}?",
+            name,
+            matches.join(", ")
+        ))
+        .into());
+    }
+
+    eprintln!("No instance is named {}.", name);
+
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Did you mean")
+        .items(&matches)
+        .default(0)
+        .interact_opt()
+        .context("Selection cancelled. Exiting.")?;
+
+    Ok(selection.map(|selection| matches[selection].clone()))
+}
+
+/// Execute the ssh invocation, waiting for it to finish or for the user to interrupt it.
+pub async fn run(invocation: String) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(invocation)
+        .spawn()
+        .context("Unable to start ssh session")?;
+
+    tokio::select! {
+        status = child.wait() => {
+            let status = status.context("Failed waiting on ssh session")?;
+            if !status.success() {
+                return Err(Error::RemoteCommand(status.code().unwrap_or(-1)).into());
+            }
         }
-        _ => {
-            println!("No choice match");
+        _ = signal::ctrl_c() => {
+            child.kill().await.context("Unable to stop ssh session")?;
         }
-    };
+    }
 
     Ok(())
 }
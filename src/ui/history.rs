@@ -0,0 +1,81 @@
+use crate::domain::connections::{self, SshConnection};
+use crate::domain::history::{Entry, History};
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+
+/// The history in the user's data directory
+pub fn managed() -> Result<History> {
+    History::managed().ok_or_else(|| anyhow!("Unable to determine data directory."))
+}
+
+/// Print the targets recently connected to with the profile, numbered as recent N expects
+pub fn recent(history: &History, profile: &str) -> Result<()> {
+    let entries = history
+        .recent(profile)
+        .context("Unable to read connection history")?;
+
+    if entries.is_empty() {
+        println!("No connections with profile {} have been recorded", profile);
+        return Ok(());
+    }
+
+    let now = Utc::now();
+    let width = entries
+        .iter()
+        .map(|entry| entry.target.len())
+        .max()
+        .unwrap_or(0);
+
+    for (i, entry) in entries.iter().enumerate() {
+        let on = match &entry.task_id {
+            Some(task_id) => format!("task {} on {}", task_id, entry.instance_id),
+            None => entry.instance_id.clone(),
+        };
+
+        println!(
+            "{:>3}. {:width$}  {:>4} ago  {}",
+            i + 1,
+            entry.target,
+            connections::age(entry.connected_at, now),
+            on,
+            width = width
+        );
+    }
+
+    Ok(())
+}
+
+/// The nth most recent target connected to with the profile, counting from 1
+pub fn target(history: &History, profile: &str, index: usize) -> Result<String> {
+    let entries = history
+        .recent(profile)
+        .context("Unable to read connection history")?;
+
+    match index.checked_sub(1).and_then(|i| entries.get(i)) {
+        Some(entry) => Ok(entry.target.clone()),
+        None if entries.is_empty() => Err(anyhow!(
+            "No connections with profile {} have been recorded",
+            profile
+        )),
+        None => Err(anyhow!(
+            "Only {} recent targets have been recorded for profile {}",
+            entries.len(),
+            profile
+        )),
+    }
+}
+
+/// Remember the connection. Failing to is not worth failing the connection over.
+pub fn record(history: &History, profile: &str, target: &str, choice: &dyn SshConnection) {
+    let entry = Entry {
+        profile: profile.into(),
+        target: target.into(),
+        instance_id: choice.instance_id().into(),
+        task_id: choice.task_id().map(String::from),
+        connected_at: Utc::now(),
+    };
+
+    if let Err(e) = history.record(entry) {
+        eprintln!("Unable to record connection history: {}", e);
+    }
+}
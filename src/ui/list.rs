@@ -1,7 +1,7 @@
 use crate::application::list_instances::Handler;
 use anyhow::{anyhow, Result};
 use itertools::Itertools;
-use prettytable::{cell, format, row, Table};
+use prettytable::{format, row, Table};
 
 pub async fn list(handler: Handler<'_>) -> Result<()> {
     let running_instances = handler.list().await?;
@@ -1,2 +1,20 @@
+pub mod completions;
+pub mod config;
 pub mod connect;
+pub mod history;
 pub mod list;
+pub mod security_groups;
+pub mod tui;
+
+use std::future::Future;
+use std::time::Instant;
+
+/// Await the lookup, logging how long it took
+pub async fn timed<T>(what: &str, lookup: impl Future<Output = T>) -> T {
+    let started = Instant::now();
+    let result = lookup.await;
+
+    tracing::info!("{} in {:.2?}", what, started.elapsed());
+
+    result
+}
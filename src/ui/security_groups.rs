@@ -0,0 +1,52 @@
+use crate::application::security_groups::{Change, Handler};
+use anyhow::Result;
+use rusoto_ec2::IpPermission;
+
+pub async fn grant(
+    handler: Handler<'_>,
+    security_group_id: String,
+    description: Option<String>,
+) -> Result<()> {
+    match handler
+        .grant_access(security_group_id.clone(), description)
+        .await?
+    {
+        Change::Applied => println!("Granted access to {}", security_group_id),
+        Change::Unchanged => println!("Access to {} was already granted", security_group_id),
+        Change::Planned(permission) => print_plan('+', &security_group_id, &permission),
+    };
+
+    Ok(())
+}
+
+pub async fn revoke(handler: Handler<'_>, security_group_id: String) -> Result<()> {
+    match handler.revoke_access(security_group_id.clone()).await? {
+        Change::Applied => println!("Revoked access to {}", security_group_id),
+        Change::Unchanged => println!("Access to {} was already revoked", security_group_id),
+        Change::Planned(permission) => print_plan('-', &security_group_id, &permission),
+    };
+
+    Ok(())
+}
+
+/// Print the ingress rule a dry run would have added (+) or removed (-)
+pub fn print_plan(sign: char, security_group_id: &str, permission: &IpPermission) {
+    println!("{}:", security_group_id);
+
+    for range in permission.ip_ranges.clone().unwrap_or_default() {
+        let mut line = format!(
+            "{} ingress {} {}-{} from {}",
+            sign,
+            permission.ip_protocol.clone().unwrap_or_default(),
+            permission.from_port.unwrap_or_default(),
+            permission.to_port.unwrap_or_default(),
+            range.cidr_ip.unwrap_or_default(),
+        );
+
+        if let Some(description) = range.description {
+            line.push_str(&format!(" ({})", description));
+        }
+
+        println!("{}", line);
+    }
+}
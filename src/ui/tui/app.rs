@@ -0,0 +1,410 @@
+use crate::domain::connections::{self, SshConnection};
+use crate::domain::inventory::{Instance, Service};
+use chrono::{DateTime, Utc};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// What is being browsed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum View {
+    Instances,
+    Services,
+    /// The containers of the opened service
+    Tasks,
+}
+
+/// What key presses do
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mode {
+    Browse,
+    /// Typing a search query, which filters the list as it is typed
+    Search,
+    /// Typing the port to tunnel to
+    Port(String),
+}
+
+/// Work the app asks the caller to do, as it needs AWS or the terminal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Quit,
+    /// Load the current profile again, after switching to it or to refresh
+    Reload,
+    /// Load the tasks of the selected service
+    OpenService,
+    Connect,
+    Tunnel(u16),
+    Grant,
+    Copy,
+}
+
+/// A listed row, indexing into the instances, services or containers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Item {
+    Instance(usize),
+    Service(usize),
+    Container(usize),
+}
+
+pub struct App {
+    pub profiles: Vec<String>,
+    profile: usize,
+    pub view: View,
+    pub mode: Mode,
+    pub query: String,
+    pub selected: usize,
+    /// The outcome of the last action, shown in place of the key help
+    pub status: Option<String>,
+    pub instances: Vec<Instance>,
+    pub services: Vec<Service>,
+    /// The service the tasks view lists the containers of
+    pub service: Option<Service>,
+    pub containers: Vec<Box<dyn SshConnection>>,
+}
+
+impl App {
+    pub fn new(profiles: Vec<String>, profile: &str) -> Self {
+        let mut profiles = profiles;
+        let position = match profiles.iter().position(|name| name == profile) {
+            Some(position) => position,
+            None => {
+                profiles.insert(0, profile.into());
+                0
+            }
+        };
+
+        Self {
+            profiles,
+            profile: position,
+            view: View::Instances,
+            mode: Mode::Browse,
+            query: String::new(),
+            selected: 0,
+            status: None,
+            instances: Vec::new(),
+            services: Vec::new(),
+            service: None,
+            containers: Vec::new(),
+        }
+    }
+
+    pub fn profile(&self) -> &str {
+        &self.profiles[self.profile]
+    }
+
+    /// Replace what was loaded for the profile
+    pub fn load(&mut self, instances: Vec<Instance>, services: Vec<Service>) {
+        self.instances = instances;
+        self.services = services;
+        self.containers.clear();
+        self.service = None;
+        if self.view == View::Tasks {
+            self.view = View::Services;
+        }
+        self.selected = 0;
+    }
+
+    /// List the containers of the selected service
+    pub fn open(&mut self, containers: Vec<Box<dyn SshConnection>>) {
+        if let Some(Item::Service(i)) = self.item() {
+            self.service = Some(self.services[i].clone());
+            self.containers = containers;
+            self.switch(View::Tasks);
+        }
+    }
+
+    /// The rows matching the search query, in the order they are listed
+    pub fn items(&self) -> Vec<Item> {
+        let now = Utc::now();
+        let query = self.query.to_lowercase();
+
+        let items: Vec<Item> = match self.view {
+            View::Instances => (0..self.instances.len()).map(Item::Instance).collect(),
+            View::Services => (0..self.services.len()).map(Item::Service).collect(),
+            View::Tasks => (0..self.containers.len()).map(Item::Container).collect(),
+        };
+
+        items
+            .into_iter()
+            .filter(|&item| {
+                self.cells(item, now)
+                    .iter()
+                    .any(|cell| cell.to_lowercase().contains(&query))
+            })
+            .collect()
+    }
+
+    pub fn item(&self) -> Option<Item> {
+        self.items().get(self.selected).copied()
+    }
+
+    /// The columns a row is listed with
+    pub fn cells(&self, item: Item, now: DateTime<Utc>) -> Vec<String> {
+        match item {
+            Item::Instance(i) => {
+                let instance = &self.instances[i];
+                vec![
+                    instance.environment.clone().unwrap_or_default(),
+                    instance.name.clone(),
+                    instance.instance_id.clone(),
+                    instance.private_ip.clone().unwrap_or_default(),
+                ]
+            }
+            Item::Service(i) => {
+                let service = &self.services[i];
+                vec![
+                    service.cluster.clone(),
+                    service.name.clone(),
+                    format!("{}/{}", service.running_count, service.desired_count),
+                ]
+            }
+            Item::Container(i) => self.containers[i].columns(now),
+        }
+    }
+
+    /// Everything known about a row, for the detail pane
+    pub fn details(&self, item: Item, now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let started = |started_at: Option<DateTime<Utc>>| {
+            started_at
+                .map(|started_at| {
+                    format!("{} ({} ago)", started_at, connections::age(started_at, now))
+                })
+                .unwrap_or_else(|| "-".into())
+        };
+
+        match item {
+            Item::Instance(i) => {
+                let instance = &self.instances[i];
+                vec![
+                    ("Name", instance.name.clone()),
+                    ("Instance id", instance.instance_id.clone()),
+                    (
+                        "Environment",
+                        instance.environment.clone().unwrap_or_else(|| "-".into()),
+                    ),
+                    (
+                        "Private ip",
+                        instance.private_ip.clone().unwrap_or_else(|| "-".into()),
+                    ),
+                    ("Availability zone", instance.availability_zone.clone()),
+                    ("Launched", started(instance.launched_at)),
+                ]
+            }
+            Item::Service(i) => {
+                let service = &self.services[i];
+                vec![
+                    ("Cluster", service.cluster.clone()),
+                    ("Service", service.name.clone()),
+                    ("Status", service.status.clone()),
+                    (
+                        "Tasks",
+                        format!(
+                            "{} running, {} desired",
+                            service.running_count, service.desired_count
+                        ),
+                    ),
+                    (
+                        "Task definition",
+                        service
+                            .task_definition
+                            .clone()
+                            .unwrap_or_else(|| "-".into()),
+                    ),
+                ]
+            }
+            Item::Container(i) => {
+                let container = &self.containers[i];
+                vec![
+                    ("Container", container.to_string()),
+                    ("Task id", container.task_id().unwrap_or("-").into()),
+                    ("Instance id", container.instance_id().into()),
+                    ("Private ip", container.private_ip().into()),
+                    ("Availability zone", container.availability_zone().into()),
+                    ("Started", started(container.started_at())),
+                ]
+            }
+        }
+    }
+
+    pub fn handle(&mut self, key: KeyEvent) -> Option<Action> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Some(Action::Quit);
+        }
+
+        match self.mode.clone() {
+            Mode::Browse => self.browse(key),
+            Mode::Search => {
+                match key.code {
+                    KeyCode::Char(c) => self.query.push(c),
+                    KeyCode::Backspace => {
+                        self.query.pop();
+                    }
+                    KeyCode::Enter => self.mode = Mode::Browse,
+                    KeyCode::Esc => {
+                        self.query.clear();
+                        self.mode = Mode::Browse;
+                    }
+                    _ => return None,
+                }
+                self.selected = 0;
+                None
+            }
+            Mode::Port(mut port) => {
+                match key.code {
+                    KeyCode::Char(c) if c.is_ascii_digit() && port.len() < 5 => port.push(c),
+                    KeyCode::Backspace => {
+                        port.pop();
+                    }
+                    KeyCode::Enter => {
+                        self.mode = Mode::Browse;
+                        return match port.parse::<u16>() {
+                            Ok(port) if port > 0 => Some(Action::Tunnel(port)),
+                            _ => {
+                                self.status = Some(format!("Invalid port {}", port));
+                                None
+                            }
+                        };
+                    }
+                    KeyCode::Esc => {
+                        self.mode = Mode::Browse;
+                        return None;
+                    }
+                    _ => {}
+                }
+                self.mode = Mode::Port(port);
+                None
+            }
+        }
+    }
+
+    fn browse(&mut self, key: KeyEvent) -> Option<Action> {
+        let count = self.items().len();
+        let connectable = matches!(
+            self.item(),
+            Some(Item::Instance(_)) | Some(Item::Container(_))
+        );
+
+        match key.code {
+            KeyCode::Char('q') => return Some(Action::Quit),
+            KeyCode::Esc | KeyCode::Left | KeyCode::Backspace if self.view == View::Tasks => {
+                self.switch(View::Services)
+            }
+            KeyCode::Esc => self.query.clear(),
+            KeyCode::Tab => match self.view {
+                View::Instances => self.switch(View::Services),
+                View::Services | View::Tasks => self.switch(View::Instances),
+            },
+            KeyCode::Char('p') | KeyCode::Char('P') if self.profiles.len() > 1 => {
+                let step = if key.code == KeyCode::Char('p') {
+                    1
+                } else {
+                    self.profiles.len() - 1
+                };
+                self.profile = (self.profile + step) % self.profiles.len();
+                return Some(Action::Reload);
+            }
+            KeyCode::Char('r') => return Some(Action::Reload),
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Down | KeyCode::Char('j') if self.selected + 1 < count => self.selected += 1,
+            KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = count.saturating_sub(1),
+            KeyCode::Enter | KeyCode::Right => match self.item() {
+                Some(Item::Service(_)) => return Some(Action::OpenService),
+                Some(_) if key.code == KeyCode::Enter => return Some(Action::Connect),
+                _ => {}
+            },
+            KeyCode::Char('c') if connectable => return Some(Action::Connect),
+            KeyCode::Char('t') if connectable => self.mode = Mode::Port(String::new()),
+            KeyCode::Char('y') if connectable => return Some(Action::Copy),
+            KeyCode::Char('g') => return Some(Action::Grant),
+            _ => {}
+        }
+
+        None
+    }
+
+    fn switch(&mut self, view: View) {
+        self.view = view;
+        self.query.clear();
+        self.selected = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, App, Item, Mode, View};
+    use crate::domain::inventory::{Instance, Service};
+    use crossterm::event::{KeyCode, KeyEvent};
+
+    fn app() -> App {
+        let mut app = App::new(vec!["production".into(), "staging".into()], "staging");
+        let instance = |environment: &str, name: &str, id: &str| Instance {
+            environment: Some(environment.into()),
+            name: name.into(),
+            instance_id: id.into(),
+            private_ip: Some("10.0.0.1".into()),
+            availability_zone: "us-east-1a".into(),
+            launched_at: None,
+        };
+
+        app.load(
+            vec![
+                instance("production", "api", "i-0001"),
+                instance("staging", "api", "i-0002"),
+                instance("staging", "worker", "i-0003"),
+            ],
+            vec![Service {
+                cluster: "staging".into(),
+                name: "api".into(),
+                status: "ACTIVE".into(),
+                running_count: 2,
+                desired_count: 2,
+                task_definition: Some("api:12".into()),
+            }],
+        );
+
+        app
+    }
+
+    fn press(app: &mut App, keys: &str) -> Option<Action> {
+        keys.chars()
+            .map(|c| app.handle(KeyEvent::from(KeyCode::Char(c))))
+            .last()
+            .flatten()
+    }
+
+    #[test]
+    fn search_filters_as_it_is_typed() {
+        let mut app = app();
+
+        press(&mut app, "/work");
+        assert_eq!(Mode::Search, app.mode);
+        assert_eq!(vec![Item::Instance(2)], app.items());
+
+        app.handle(KeyEvent::from(KeyCode::Esc));
+        assert_eq!(3, app.items().len());
+    }
+
+    #[test]
+    fn keys_ask_for_actions_on_the_selection() {
+        let mut app = app();
+
+        assert_eq!(Some(Action::Reload), press(&mut app, "p"));
+        assert_eq!("production", app.profile());
+
+        press(&mut app, "j");
+        assert_eq!(Some(Action::Copy), press(&mut app, "y"));
+        assert_eq!(None, press(&mut app, "t8080"));
+        assert_eq!(
+            Some(Action::Tunnel(8080)),
+            app.handle(KeyEvent::from(KeyCode::Enter))
+        );
+
+        app.handle(KeyEvent::from(KeyCode::Tab));
+        assert_eq!(View::Services, app.view);
+        assert_eq!(
+            Some(Action::OpenService),
+            app.handle(KeyEvent::from(KeyCode::Enter))
+        );
+        assert_eq!(None, press(&mut app, "c"));
+    }
+}
@@ -0,0 +1,322 @@
+use crate::application::security_groups::{self, Change};
+use crate::application::{connect, inventory, Clients};
+use crate::domain::cache::Cache;
+use crate::domain::connections::{HostConnection, SshConnection, SshSettings};
+use crate::domain::target::Target;
+use crate::settings::{Profile, Settings};
+use crate::ui;
+use anyhow::{anyhow, Result};
+use app::{Action, App, Item};
+use crossterm::event::{self, Event, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::CrosstermBackend;
+use ratatui::Terminal;
+use std::io::{self, Stdout, Write};
+
+mod app;
+mod view;
+
+type Screen = Terminal<CrosstermBackend<Stdout>>;
+
+/// Browse the instances and services of each profile, connecting to them from a full screen ui
+pub async fn run(settings: &Settings, profile: &str, dry_run: bool) -> Result<()> {
+    let mut app = App::new(settings.profile_names(), profile);
+    let mut screen = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    restore_on_panic();
+
+    let result = browse(&mut screen, &mut app, settings, dry_run).await;
+    leave(&mut screen)?;
+
+    result
+}
+
+async fn browse(
+    screen: &mut Screen,
+    app: &mut App,
+    settings: &Settings,
+    dry_run: bool,
+) -> Result<()> {
+    loop {
+        let profile = settings.profile(app.profile())?;
+        let clients = Clients::new(app.profile(), &profile)?;
+        let ssh_settings = profile.ssh_settings()?;
+        let cache = Cache::managed(app.profile(), &profile.aws_region, profile.cache_ttl, false);
+        let handler = connect::Handler::new(
+            &clients.ecs,
+            &clients.ec2,
+            &clients.instance_connect,
+            &cache,
+        );
+
+        // Loading may need to ask for an MFA code, so it happens outside of the ui
+        leave(screen)?;
+        println!("Loading {}...", app.profile());
+        load(app, &clients, &cache).await;
+
+        loop {
+            enter(screen)?;
+            screen.draw(|frame| view::draw(frame, app))?;
+
+            let key = match tokio::task::block_in_place(event::read)? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+
+            let action = match app.handle(key) {
+                Some(action) => action,
+                None => continue,
+            };
+            app.status = None;
+
+            let result = match action {
+                Action::Quit => return Ok(()),
+                Action::Reload => break,
+                Action::OpenService => open_service(app, &handler).await,
+                Action::Grant => grant(&clients, &profile, dry_run).await,
+                Action::Connect | Action::Tunnel(_) | Action::Copy => {
+                    let mut host = None;
+                    let (target, choice) = match (selected_target(app), selected(app, &mut host)) {
+                        (Some(target), Some(choice)) => (target, choice),
+                        _ => continue,
+                    };
+
+                    // The list was loaded a while ago, and may have been cached before that
+                    match ui::connect::stopped(&handler, &target, choice).await {
+                        Ok(false) => {}
+                        Ok(true) => {
+                            app.status = Some(format!(
+                                "{} is no longer running. Press r to refresh.",
+                                choice
+                            ));
+                            continue;
+                        }
+                        Err(e) => {
+                            app.status = Some(format!("Error: {:#}", e));
+                            continue;
+                        }
+                    }
+
+                    match action {
+                        Action::Copy => copy(&handler, &ssh_settings, choice, dry_run)
+                            .await
+                            .map(Some),
+                        _ => {
+                            leave(screen)?;
+                            session(
+                                &handler,
+                                &clients,
+                                &profile,
+                                &ssh_settings,
+                                choice,
+                                action,
+                                dry_run,
+                            )
+                            .await
+                        }
+                    }
+                }
+            };
+
+            match result {
+                Ok(status) => app.status = status,
+                Err(e) => app.status = Some(format!("Error: {:#}", e)),
+            }
+        }
+    }
+}
+
+/// Load the instances and services of the profile, keeping whatever could be loaded
+async fn load(app: &mut App, clients: &Clients, cache: &Cache) {
+    let handler = inventory::Handler::new(&clients.ec2, &clients.ecs, cache);
+    let mut errors = Vec::new();
+
+    let instances = handler.instances().await.unwrap_or_else(|e| {
+        errors.push(format!("{:#}", e));
+        Vec::new()
+    });
+    let services = handler.services().await.unwrap_or_else(|e| {
+        errors.push(format!("{:#}", e));
+        Vec::new()
+    });
+
+    app.load(instances, services);
+    app.status = if errors.is_empty() {
+        None
+    } else {
+        Some(format!("Error: {}", errors.join("; ")))
+    };
+}
+
+async fn open_service(app: &mut App, handler: &connect::Handler<'_>) -> Result<Option<String>> {
+    let service = match app.item() {
+        Some(Item::Service(i)) => app.services[i].clone(),
+        _ => return Ok(None),
+    };
+
+    let containers = handler
+        .service_containers(&service.cluster, &service.name)
+        .await?;
+    app.open(containers);
+
+    Ok(None)
+}
+
+/// The selected instance or container. Instances are only connectable once turned into a
+/// connection, which is kept in host.
+fn selected<'a>(
+    app: &'a App,
+    host: &'a mut Option<HostConnection>,
+) -> Option<&'a dyn SshConnection> {
+    match app.item()? {
+        Item::Instance(i) => {
+            *host = app.instances[i].connection();
+            host.as_ref().map(|host| host as &dyn SshConnection)
+        }
+        Item::Container(i) => Some(app.containers[i].as_ref()),
+        Item::Service(_) => None,
+    }
+}
+
+/// The target the selected instance or container was looked up as
+fn selected_target(app: &App) -> Option<Target> {
+    match app.item()? {
+        Item::Instance(i) => Some(Target::InstanceId(app.instances[i].instance_id.clone())),
+        Item::Container(_) => app.service.as_ref().map(|service| Target::Service {
+            cluster: service.cluster.clone(),
+            service: service.name.clone(),
+            container: None,
+        }),
+        Item::Service(_) => None,
+    }
+}
+
+async fn grant(clients: &Clients, profile: &Profile, dry_run: bool) -> Result<Option<String>> {
+    let handler = security_groups::Handler::new(&clients.ec2, dry_run);
+    let security_group_id = profile.security_group_id.clone();
+
+    let status = match handler
+        .grant_access(security_group_id.clone(), Some("heimdallr session".into()))
+        .await?
+    {
+        Change::Applied => format!("Granted access to {}", security_group_id),
+        Change::Unchanged => format!("Access to {} was already granted", security_group_id),
+        Change::Planned(permission) => format!(
+            "Would grant {} access to {}",
+            permission
+                .ip_ranges
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|range| range.cidr_ip)
+                .collect::<Vec<String>>()
+                .join(", "),
+            security_group_id
+        ),
+    };
+
+    Ok(Some(status))
+}
+
+/// Connect or tunnel to the choice outside of the ui, returning once the session ends
+async fn session(
+    handler: &connect::Handler<'_>,
+    clients: &Clients,
+    profile: &Profile,
+    settings: &SshSettings,
+    choice: &dyn SshConnection,
+    action: Action,
+    dry_run: bool,
+) -> Result<Option<String>> {
+    let prepared = match dry_run {
+        true => ui::connect::planned(settings)?,
+        false => ui::connect::prepare(handler, settings, choice).await?,
+    };
+    let settings = &prepared.settings;
+
+    let invocation = match action {
+        Action::Tunnel(port) => {
+            println!(
+                "Forwarding localhost:{port} to {ip}:{port}. Press Ctrl-C to close the tunnel.",
+                port = port,
+                ip = choice.private_ip()
+            );
+            settings.tunnel(choice.private_ip(), port, port)
+        }
+        _ => choice.connection(settings, vec!["bash".into()]),
+    };
+
+    if dry_run {
+        return Ok(Some(invocation));
+    }
+
+    if profile.auto_grant {
+        ui::connect::run_with_grant(
+            security_groups::Handler::new(&clients.ec2, dry_run),
+            profile.security_group_id.clone(),
+            invocation,
+            dry_run,
+        )
+        .await?;
+    } else {
+        ui::connect::run(invocation).await?;
+    }
+
+    Ok(Some(format!("Disconnected from {}", choice)))
+}
+
+/// Copy the ssh command for the choice to the clipboard of the terminal
+async fn copy(
+    handler: &connect::Handler<'_>,
+    settings: &SshSettings,
+    choice: &dyn SshConnection,
+    dry_run: bool,
+) -> Result<String> {
+    let prepared = match dry_run {
+        true => ui::connect::planned(settings)?,
+        false => ui::connect::prepare(handler, settings, choice).await?,
+    };
+    let invocation = choice.connection(&prepared.settings, vec!["bash".into()]);
+    // The copied command is run later, so its key has to outlive this connection
+    prepared.keep();
+
+    // OSC 52 asks the terminal to set its clipboard, which works over ssh and without a display
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", base64::encode(&invocation))
+        .and_then(|_| stdout.flush())
+        .map_err(|e| anyhow!("Unable to copy the ssh command: {}", e))?;
+
+    Ok(format!("Copied the ssh command for {}", choice))
+}
+
+/// Leave the ui before a panic is reported, so the message is readable and the terminal is not
+/// left in raw mode
+fn restore_on_panic() {
+    let report = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        if let Ok(mut screen) = Terminal::new(CrosstermBackend::new(io::stdout())) {
+            let _ = leave(&mut screen);
+        }
+        report(info);
+    }));
+}
+
+fn enter(screen: &mut Screen) -> Result<()> {
+    if !terminal::is_raw_mode_enabled()? {
+        terminal::enable_raw_mode()?;
+        execute!(screen.backend_mut(), EnterAlternateScreen)?;
+        screen.clear()?;
+    }
+
+    Ok(())
+}
+
+fn leave(screen: &mut Screen) -> Result<()> {
+    if terminal::is_raw_mode_enabled()? {
+        terminal::disable_raw_mode()?;
+        execute!(screen.backend_mut(), LeaveAlternateScreen)?;
+        screen.show_cursor()?;
+    }
+
+    Ok(())
+}
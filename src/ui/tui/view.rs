@@ -0,0 +1,204 @@
+use super::app::{App, Mode, View};
+use chrono::Utc;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Paragraph, Row, Table, TableState, Tabs};
+use ratatui::Frame;
+
+const HELP: &str = "/ search  tab switch view  p profile  enter open/connect  c connect  t tunnel  g grant  y copy ssh  r refresh  q quit";
+
+pub fn draw(frame: &mut Frame, app: &App) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list, detail] =
+        Layout::horizontal([Constraint::Percentage(65), Constraint::Percentage(35)]).areas(body);
+
+    draw_header(frame, app, header);
+    draw_list(frame, app, list);
+    draw_detail(frame, app, detail);
+    draw_footer(frame, app, footer);
+}
+
+fn draw_header(frame: &mut Frame, app: &App, area: Rect) {
+    let services = match (&app.view, &app.service) {
+        (View::Tasks, Some(service)) => format!("Services › {}#{}", service.cluster, service.name),
+        _ => "Services".into(),
+    };
+    let selected = match app.view {
+        View::Instances => 0,
+        View::Services | View::Tasks => 1,
+    };
+
+    let [profile, views] = Layout::horizontal([
+        Constraint::Length(app.profile().len() as u16 + 12),
+        Constraint::Min(0),
+    ])
+    .areas(area);
+
+    frame.render_widget(
+        Paragraph::new(Line::from(vec![
+            Span::raw(" Profile: "),
+            Span::styled(
+                app.profile(),
+                Style::default()
+                    .fg(Color::Blue)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        ])),
+        profile,
+    );
+    frame.render_widget(
+        Tabs::new(vec!["Instances".to_string(), services])
+            .select(selected)
+            .highlight_style(
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ),
+        views,
+    );
+}
+
+fn draw_list(frame: &mut Frame, app: &App, area: Rect) {
+    let now = Utc::now();
+    let items = app.items();
+    let mut rows: Vec<Vec<String>> = items.iter().map(|&item| app.cells(item, now)).collect();
+
+    // Group instances by environment, like the list command, naming each environment once
+    if app.view == View::Instances {
+        for i in (1..rows.len()).rev() {
+            if rows[i][0] == rows[i - 1][0] {
+                rows[i][0] = String::new();
+            }
+        }
+    }
+
+    let mut widths: Vec<u16> = Vec::new();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            let width = cell.chars().count() as u16;
+            match widths.get_mut(i) {
+                Some(max) => *max = (*max).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    let title = match app.view {
+        View::Instances => " Instances ",
+        View::Services => " Services ",
+        View::Tasks => " Tasks ",
+    };
+    let table = Table::new(
+        rows.into_iter().map(Row::new),
+        widths.into_iter().map(Constraint::Length),
+    )
+    .block(Block::default().borders(Borders::ALL).title(title))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+    .highlight_symbol("› ");
+
+    let mut state = TableState::default();
+    if !items.is_empty() {
+        state.select(Some(app.selected));
+    }
+
+    frame.render_stateful_widget(table, area, &mut state);
+}
+
+fn draw_detail(frame: &mut Frame, app: &App, area: Rect) {
+    let lines: Vec<Line> = match app.item() {
+        Some(item) => app
+            .details(item, Utc::now())
+            .into_iter()
+            .map(|(label, value)| {
+                Line::from(vec![
+                    Span::styled(format!("{}: ", label), Style::default().fg(Color::Cyan)),
+                    Span::raw(value),
+                ])
+            })
+            .collect(),
+        None => vec![Line::from(empty(app))],
+    };
+
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Details ")),
+        area,
+    );
+}
+
+fn draw_footer(frame: &mut Frame, app: &App, area: Rect) {
+    let line = match &app.mode {
+        Mode::Search => Line::from(format!("/{}", app.query)),
+        Mode::Port(port) => Line::from(format!("Tunnel to port: {}", port)),
+        Mode::Browse => match &app.status {
+            Some(status) => Line::from(status.as_str()),
+            None if !app.query.is_empty() => Line::from(format!("/{}  (esc clears)", app.query)),
+            None => Line::from(Span::styled(HELP, Style::default().fg(Color::DarkGray))),
+        },
+    };
+
+    frame.render_widget(Paragraph::new(line), area);
+}
+
+fn empty(app: &App) -> &'static str {
+    match (app.query.is_empty(), app.view) {
+        (false, _) => "Nothing matches the search",
+        (true, View::Instances) => "No running instances",
+        (true, View::Services) => "No ecs services",
+        (true, View::Tasks) => "No running tasks",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::draw;
+    use crate::domain::inventory::Instance;
+    use crate::ui::tui::app::App;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn rendered(app: &App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 12)).unwrap();
+        terminal.draw(|frame| draw(frame, app)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        buffer
+            .content()
+            .chunks(buffer.area.width as usize)
+            .map(|line| line.iter().map(|cell| cell.symbol()).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn instances_are_grouped_by_environment_with_details_of_the_selection() {
+        let mut app = App::new(vec!["staging".into()], "staging");
+        let instance = |name: &str, id: &str| Instance {
+            environment: Some("staging".into()),
+            name: name.into(),
+            instance_id: id.into(),
+            private_ip: Some("10.0.0.1".into()),
+            availability_zone: "us-east-1a".into(),
+            launched_at: None,
+        };
+        app.load(
+            vec![instance("api", "i-0001"), instance("worker", "i-0002")],
+            Vec::new(),
+        );
+
+        let screen = rendered(&app);
+
+        assert!(screen.contains("Profile: staging"));
+        assert!(screen.contains("› staging api    i-0001 10.0.0.1"));
+        assert!(screen.contains("          worker i-0002 10.0.0.1"));
+        assert!(screen.contains("Instance id: i-0001"));
+
+        app.query = "nothing".into();
+        assert!(rendered(&app).contains("Nothing matches the search"));
+    }
+}